@@ -0,0 +1,153 @@
+use crate::notmuch::{self, Attachment};
+use log::*;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+// file name safe to join to a directory, attachment names are sender controlled
+pub fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+    let mut result: String = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            _ => c,
+        })
+        .collect();
+    result = result.trim().trim_start_matches('.').to_string();
+
+    // keep within common file name length limit
+    while result.len() > 200 {
+        result.pop();
+    }
+
+    match result.is_empty() {
+        true => "attachment".to_string(),
+        _ => result,
+    }
+}
+
+pub fn expand_dir(dir: &str) -> PathBuf {
+    match dirs::home_dir() {
+        Some(home) if dir == "~" || dir.starts_with("~/") => {
+            home.join(dir[1..].trim_start_matches('/'))
+        }
+        _ => PathBuf::from(dir),
+    }
+}
+
+pub fn default_dir(configured: Option<&str>) -> String {
+    match configured {
+        Some(dir) => dir.to_string(),
+        None => dirs::download_dir()
+            .or_else(dirs::home_dir)
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_else(|| ".".to_string()),
+    }
+}
+
+fn candidate(name: &str, n: usize) -> String {
+    if n == 0 {
+        return name.to_string();
+    }
+    match name.rfind('.') {
+        Some(pos) if pos > 0 => format!("{} ({}){}", &name[..pos], n, &name[pos..]),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+// write to 'dir/name', never overwriting, returns path written
+pub fn write_new(dir: &Path, name: &str, data: &[u8]) -> Result<PathBuf, failure::Error> {
+    let name = sanitize_filename(name);
+
+    for n in 0..1000 {
+        let path = dir.join(candidate(&name, n));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(mut f) => {
+                f.write_all(data)?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => failure::bail!("{}: {}", path.display(), e),
+        }
+    }
+
+    failure::bail!("Too many files named {} in {}", name, dir.display())
+}
+
+pub fn content(id: &str, attachment: &Attachment) -> Result<Vec<u8>, failure::Error> {
+    match attachment {
+        Attachment::File(part, _, _, _) => notmuch::raw_part(id, *part),
+        Attachment::Html(s, _) => Ok(s.as_bytes().to_vec()),
+    }
+}
+
+pub fn filename(id: &str, attachment: &Attachment) -> String {
+    match attachment {
+        Attachment::File(_, fname, _, _) => sanitize_filename(fname),
+        Attachment::Html(_, _) => sanitize_filename(&format!("{}.html", id)),
+    }
+}
+
+pub fn save(id: &str, attachment: &Attachment, dir: &Path) -> Result<PathBuf, failure::Error> {
+    debug!("attachment::save: {:?}", dir);
+
+    fs::create_dir_all(dir)?;
+    write_new(dir, &filename(id, attachment), &content(id, attachment)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_and_reserved_characters_are_removed() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\temp\\a:b?.txt"), "a_b_.txt");
+        assert_eq!(sanitize_filename(" ..hidden\n"), "hidden");
+        assert_eq!(sanitize_filename("räksmörgås 🎉.pdf"), "räksmörgås 🎉.pdf");
+    }
+
+    #[test]
+    fn empty_names_get_a_default() {
+        assert_eq!(sanitize_filename(""), "attachment");
+        assert_eq!(sanitize_filename("dir/"), "attachment");
+        assert_eq!(sanitize_filename("..."), "attachment");
+    }
+
+    #[test]
+    fn long_names_are_cut_on_character_boundary() {
+        let name = sanitize_filename(&"ä".repeat(150));
+        assert!(name.len() <= 200);
+        assert_eq!(name, "ä".repeat(100));
+    }
+
+    #[test]
+    fn candidates_are_numbered_before_extension() {
+        assert_eq!(candidate("a.txt", 0), "a.txt");
+        assert_eq!(candidate("a.txt", 2), "a (2).txt");
+        assert_eq!(candidate(".profile", 1), ".profile (1)");
+        assert_eq!(candidate("noext", 1), "noext (1)");
+    }
+
+    #[test]
+    fn existing_files_are_not_overwritten() {
+        let dir = std::env::temp_dir().join(format!("nutt-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = write_new(&dir, "a.txt", b"1").unwrap();
+        let second = write_new(&dir, "a.txt", b"2").unwrap();
+        assert_eq!(first, dir.join("a.txt"));
+        assert_eq!(second, dir.join("a (1).txt"));
+        assert_eq!(fs::read(&first).unwrap(), b"1");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
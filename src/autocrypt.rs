@@ -0,0 +1,291 @@
+use crate::compose;
+use crate::notmuch;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// parsed 'Autocrypt:' header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub addr: String,
+    pub prefer_encrypt: bool,
+    pub keydata: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Peer {
+    pub last_seen: u64,
+    pub autocrypt_timestamp: u64,
+    // base64 encoded public key
+    pub keydata: Option<String>,
+    pub prefer_encrypt: bool,
+}
+
+pub enum Recommendation {
+    Disable,
+    Available,
+    Encrypt,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Store {
+    peers: HashMap<String, Peer>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+pub fn parse_header(value: &str) -> Option<Header> {
+    let mut addr = None;
+    let mut prefer_encrypt = false;
+    let mut keydata = None;
+
+    for attr in value.split(';') {
+        let attr = attr.trim();
+        let pos = attr.find('=')?;
+        let (name, val) = (attr[..pos].trim(), attr[pos + 1..].trim());
+        match name {
+            "addr" => addr = Some(val.to_lowercase()),
+            "prefer-encrypt" => prefer_encrypt = val == "mutual",
+            "keydata" => keydata = Some(val.split_whitespace().collect::<String>()),
+            // unknown non-critical attributes start with underscore
+            _ if name.starts_with('_') => {}
+            _ => return None,
+        }
+    }
+
+    Some(Header {
+        addr: addr?,
+        prefer_encrypt,
+        keydata: keydata?,
+    })
+}
+
+// header value for outgoing messages, folded to fit line limits
+pub fn make_header(addr: &str, prefer_encrypt: bool, key: &[u8]) -> String {
+    let mut result = format!("addr={};", addr);
+    if prefer_encrypt {
+        result.push_str(" prefer-encrypt=mutual;");
+    }
+    result.push_str(" keydata=");
+
+    let keydata = base64::encode(key);
+    // key starts on its own line so the first one isn't longer
+    for chunk in keydata.as_bytes().chunks(72) {
+        result.push_str("\r\n ");
+        result.push_str(&String::from_utf8_lossy(chunk));
+    }
+
+    result
+}
+
+fn store_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("nutt").join("autocrypt.json"))
+}
+
+impl Store {
+    pub fn load() -> Result<Store, failure::Error> {
+        match store_path() {
+            Some(path) if path.exists() => {
+                debug!("autocrypt::Store::load: {:?}", &path);
+                Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+            }
+            _ => Ok(Store::default()),
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), failure::Error> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(path) = store_path() {
+            debug!("autocrypt::Store::save: {:?}", &path);
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, serde_json::to_vec(self)?)?;
+        }
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    pub fn peer(&self, addr: &str) -> Option<&Peer> {
+        self.peers.get(&addr.to_lowercase())
+    }
+
+    // Autocrypt Level 1 peer state update
+    pub fn update(&mut self, msg: &notmuch::Message) {
+        let from = match msg.headers.get("From") {
+            Some(from) => match compose::addresses(from).into_iter().next() {
+                Some(addr) => addr.to_lowercase(),
+                None => return,
+            },
+            None => return,
+        };
+        let header = msg
+            .headers
+            .get("Autocrypt")
+            .and_then(|value| parse_header(value))
+            .filter(|header| header.addr == from);
+
+        let peer = self.peers.entry(from).or_default();
+        if msg.timestamp <= peer.last_seen {
+            return;
+        }
+        peer.last_seen = msg.timestamp;
+
+        if let Some(header) = header {
+            if msg.timestamp > peer.autocrypt_timestamp {
+                peer.autocrypt_timestamp = msg.timestamp;
+                peer.keydata = Some(header.keydata);
+                peer.prefer_encrypt = header.prefer_encrypt;
+            }
+        }
+        self.dirty = true;
+    }
+
+    pub fn key(&self, addr: &str) -> Option<Vec<u8>> {
+        self.peer(addr)
+            .and_then(|peer| peer.keydata.as_ref())
+            .and_then(|keydata| base64::decode(keydata).ok())
+    }
+
+    pub fn recommend(&self, recipients: &[String], prefer_encrypt: bool) -> Recommendation {
+        if recipients.is_empty() || recipients.iter().any(|r| self.key(r).is_none()) {
+            return Recommendation::Disable;
+        }
+
+        let mutual = recipients
+            .iter()
+            .filter_map(|r| self.peer(r))
+            .all(|peer| peer.prefer_encrypt);
+        match mutual && prefer_encrypt {
+            true => Recommendation::Encrypt,
+            _ => Recommendation::Available,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &str, timestamp: u64, autocrypt: Option<&str>) -> notmuch::Message {
+        let mut headers = serde_json::json!({ "From": from });
+        if let Some(value) = autocrypt {
+            headers["Autocrypt"] = value.into();
+        }
+        serde_json::from_value(serde_json::json!({
+            "id": "id@example.com",
+            "filename": [],
+            "timestamp": timestamp,
+            "date_relative": "today",
+            "tags": [],
+            "body": [],
+            "headers": headers,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn header_attributes() {
+        let header =
+            parse_header("addr=Alice@Example.com; prefer-encrypt=mutual; keydata=QUJD\r\n REVG")
+                .unwrap();
+        assert_eq!(header.addr, "alice@example.com");
+        assert!(header.prefer_encrypt);
+        assert_eq!(header.keydata, "QUJDREVG");
+
+        let header = parse_header("addr=a@b; _note=ignored; keydata=QUJD").unwrap();
+        assert!(!header.prefer_encrypt);
+    }
+
+    #[test]
+    fn broken_headers_are_rejected() {
+        assert_eq!(parse_header("addr=a@b; critical=yes; keydata=QUJD"), None);
+        assert_eq!(parse_header("keydata=QUJD"), None);
+        assert_eq!(parse_header("addr=a@b"), None);
+        assert_eq!(parse_header("addr=a@b; keydata"), None);
+        assert_eq!(parse_header(""), None);
+    }
+
+    #[test]
+    fn made_header_parses_back() {
+        let key = vec![0xffu8; 200];
+        let value = make_header("ä@example.com", true, &key);
+        assert!(value.split("\r\n").all(|line| line.len() <= 78));
+        let header = parse_header(&value).unwrap();
+        assert_eq!(base64::decode(&header.keydata).unwrap(), key);
+        assert!(header.prefer_encrypt);
+    }
+
+    #[test]
+    fn newer_messages_update_peer() {
+        let mut store = Store::default();
+        store.update(&message(
+            "Alice <alice@example.com>",
+            10,
+            Some("addr=alice@example.com; keydata=QUJD"),
+        ));
+        assert_eq!(store.key("ALICE@example.com"), Some(b"ABC".to_vec()));
+        assert!(store.dirty);
+
+        // older message doesn't replace the key
+        store.update(&message(
+            "alice@example.com",
+            5,
+            Some("addr=alice@example.com; keydata=REVG"),
+        ));
+        assert_eq!(store.key("alice@example.com"), Some(b"ABC".to_vec()));
+
+        // newer message without header keeps the key
+        store.update(&message("alice@example.com", 20, None));
+        let peer = store.peer("alice@example.com").unwrap();
+        assert_eq!((peer.last_seen, peer.autocrypt_timestamp), (20, 10));
+        assert_eq!(store.key("alice@example.com"), Some(b"ABC".to_vec()));
+    }
+
+    #[test]
+    fn header_for_other_address_is_ignored() {
+        let mut store = Store::default();
+        store.update(&message(
+            "mallory@example.com",
+            10,
+            Some("addr=alice@example.com; keydata=QUJD"),
+        ));
+        assert_eq!(store.key("alice@example.com"), None);
+        assert_eq!(store.key("mallory@example.com"), None);
+        assert_eq!(store.peer("mallory@example.com").unwrap().last_seen, 10);
+    }
+
+    #[test]
+    fn recommendations() {
+        let mut store = Store::default();
+        store.update(&message(
+            "a@example.com",
+            1,
+            Some("addr=a@example.com; prefer-encrypt=mutual; keydata=QUJD"),
+        ));
+        store.update(&message(
+            "b@example.com",
+            1,
+            Some("addr=b@example.com; keydata=QUJD"),
+        ));
+        let recipients = |list: &[&str]| list.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+
+        let check = |list: &[&str], prefer: bool| match store.recommend(&recipients(list), prefer) {
+            Recommendation::Disable => "disable",
+            Recommendation::Available => "available",
+            Recommendation::Encrypt => "encrypt",
+        };
+        assert_eq!(check(&[], true), "disable");
+        assert_eq!(check(&["a@example.com", "c@example.com"], true), "disable");
+        assert_eq!(check(&["a@example.com"], true), "encrypt");
+        assert_eq!(check(&["a@example.com"], false), "available");
+        assert_eq!(
+            check(&["a@example.com", "b@example.com"], true),
+            "available"
+        );
+    }
+}
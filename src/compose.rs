@@ -0,0 +1,579 @@
+use crate::autocrypt;
+use crate::config::{Config, Identity};
+use crate::flowed;
+use crate::pgp;
+use failure::bail;
+use log::*;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// attached file or forwarded message
+pub struct Part {
+    pub name: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+pub struct Draft {
+    pub from: String,
+    pub to: String,
+    pub cc: String,
+    pub subject: String,
+    pub body: String,
+    pub sign: bool,
+    pub encrypt: bool,
+    // iCalendar object sent as text/calendar alternative
+    pub calendar: Option<String>,
+    pub attachments: Vec<Part>,
+}
+
+impl Draft {
+    pub fn new(identity: &Identity) -> Self {
+        Draft {
+            from: identity.mailbox(),
+            to: "".to_string(),
+            cc: "".to_string(),
+            subject: "".to_string(),
+            body: "".to_string(),
+            sign: identity.sign,
+            encrypt: identity.encrypt,
+            calendar: None,
+            attachments: vec![],
+        }
+    }
+
+    // text presented in the editor
+    pub fn template(&self) -> String {
+        format!(
+            "From: {}\nTo: {}\nCc: {}\nSubject: {}\n\n{}",
+            self.from, self.to, self.cc, self.subject, self.body
+        )
+    }
+
+    // read back edited template, unknown headers are ignored
+    pub fn update(&mut self, text: &str) {
+        let mut lines = text.lines();
+
+        for line in &mut lines {
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some(pos) = line.find(':') {
+                let value = line[pos + 1..].trim().to_string();
+                match line[..pos].trim().to_lowercase().as_str() {
+                    "from" => self.from = value,
+                    "to" => self.to = value,
+                    "cc" => self.cc = value,
+                    "subject" => self.subject = value,
+                    _ => warn!("Draft::update: unknown header: {}", line),
+                }
+            }
+        }
+
+        self.body = lines.collect::<Vec<&str>>().join("\n");
+        if !self.body.ends_with('\n') {
+            self.body.push('\n');
+        }
+    }
+
+    pub fn recipients(&self) -> Vec<String> {
+        let mut result = addresses(&self.to);
+        result.extend(addresses(&self.cc));
+        result
+    }
+
+    pub fn sender(&self) -> String {
+        addresses(&self.from)
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| self.from.to_string())
+    }
+}
+
+// mailboxes of header value split at commas outside quotes
+fn mailboxes(value: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for ch in value.chars().chain(std::iter::once(',')) {
+        match ch {
+            '"' => {
+                quoted = !quoted;
+                current.push(ch);
+            }
+            ',' if !quoted => {
+                if !current.trim().is_empty() {
+                    result.push(current.trim().to_string());
+                }
+                current.clear();
+            }
+            _ => current.push(ch),
+        }
+    }
+
+    result
+}
+
+// bare addresses from header value like 'Name <a@b>, c@d'
+pub fn addresses(value: &str) -> Vec<String> {
+    mailboxes(value)
+        .iter()
+        .filter_map(|item| {
+            let addr = match (item.rfind('<'), item.rfind('>')) {
+                (Some(start), Some(end)) if start < end => &item[start + 1..end],
+                _ => item,
+            };
+            match addr.contains('@') {
+                true => Some(addr.trim().to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// RFC 2047 Q-encoded words for non-ascii text, each at most 75
+// characters and using only characters allowed in a phrase
+fn encoded_words(text: &str) -> Vec<String> {
+    if text.is_ascii() {
+        return vec![text.to_string()];
+    }
+
+    const START: &str = "=?utf-8?Q?";
+    const END: &str = "?=";
+    let mut result = vec![];
+    let mut word = String::new();
+    let mut buf = [0; 4];
+    for ch in text.chars() {
+        // characters are never split between words
+        let encoded: String = match ch {
+            ' ' => "_".to_string(),
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '!' | '*' | '+' | '-' | '/' => ch.to_string(),
+            _ => ch
+                .encode_utf8(&mut buf)
+                .bytes()
+                .map(|b| format!("={:02X}", b))
+                .collect(),
+        };
+        if START.len() + word.len() + encoded.len() + END.len() > 75 {
+            result.push(format!("{}{}{}", START, word, END));
+            word.clear();
+        }
+        word.push_str(&encoded);
+    }
+    result.push(format!("{}{}{}", START, word, END));
+
+    result
+}
+
+// unstructured header value, folded between encoded-words
+fn encode_header(value: &str) -> String {
+    encoded_words(value).join("\r\n ")
+}
+
+// address list header, only display names are encoded so that
+// addresses and separating commas stay parseable
+fn encode_addresses(value: &str) -> String {
+    mailboxes(value)
+        .iter()
+        .map(|item| match item.rfind('<') {
+            Some(pos) if !item[..pos].is_ascii() => {
+                let name = item[..pos].trim().trim_matches('"');
+                format!("{} {}", encode_header(name), &item[pos..])
+            }
+            _ => item.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn quoted_printable(text: &str) -> String {
+    let mut result = String::new();
+
+    for line in text.lines() {
+        let mut encoded = String::new();
+        let bytes = line.as_bytes();
+        for (i, b) in bytes.iter().enumerate() {
+            let chunk = match b {
+                // trailing whitespace and 'From ' must be encoded for signing
+                b' ' | b'\t' if i == bytes.len() - 1 => format!("={:02X}", b),
+                b'F' if i == 0 && line.starts_with("From ") => "=46".to_string(),
+                b'=' => "=3D".to_string(),
+                33..=126 | b' ' | b'\t' => (*b as char).to_string(),
+                _ => format!("={:02X}", b),
+            };
+            if encoded.len() + chunk.len() > 75 {
+                result.push_str(&encoded);
+                result.push_str("=\r\n");
+                encoded.clear();
+            }
+            encoded.push_str(&chunk);
+        }
+        result.push_str(&encoded);
+        result.push_str("\r\n");
+    }
+
+    result
+}
+
+fn boundary() -> String {
+    let now = chrono::Local::now();
+    format!("nutt-{}-{}", now.timestamp_nanos(), std::process::id())
+}
+
+fn message_id(sender: &str) -> String {
+    let domain = sender.rsplit('@').next().unwrap_or("localhost");
+    format!(
+        "<{}.{}@{}>",
+        chrono::Local::now().timestamp_nanos(),
+        std::process::id(),
+        domain
+    )
+}
+
+fn text_part(body: &str, flowed: bool) -> String {
+    match flowed {
+        true => format!(
+            "Content-Type: text/plain; charset=utf-8; format=flowed\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\r\n{}",
+            quoted_printable(&flowed::encode(body))
+        ),
+        _ => format!(
+            "Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\r\n{}",
+            quoted_printable(body)
+        ),
+    }
+}
+
+// iTIP method is repeated in the content type
+fn calendar_part(calendar: &str) -> String {
+    let method = calendar
+        .lines()
+        .find(|line| line.starts_with("METHOD:"))
+        .map(|line| line[7..].trim().to_string())
+        .unwrap_or_else(|| "PUBLISH".to_string());
+
+    format!(
+        "Content-Type: text/calendar; charset=utf-8; method={}\r\n\
+         Content-Transfer-Encoding: quoted-printable\r\n\r\n{}",
+        method,
+        quoted_printable(calendar)
+    )
+}
+
+fn alternative_part(body: &str, flowed: bool, calendar: &str) -> String {
+    format!(
+        "Content-Type: multipart/alternative; boundary=\"{b}\"\r\n\r\n\
+         --{b}\r\n{text}\r\n--{b}\r\n{calendar}\r\n--{b}--\r\n",
+        b = boundary(),
+        text = text_part(body, flowed),
+        calendar = calendar_part(calendar),
+    )
+}
+
+fn attachment_part(part: &Part) -> String {
+    let name = encoded_words(&part.name.replace('"', "")).join(" ");
+
+    // forwarded messages are kept readable, only line endings normalized
+    if part.content_type == "message/rfc822" {
+        let data = String::from_utf8_lossy(&part.data)
+            .replace("\r\n", "\n")
+            .replace('\n', "\r\n");
+        return format!(
+            "Content-Type: message/rfc822\r\n\
+             Content-Disposition: inline; filename=\"{}\"\r\n\r\n{}",
+            name, data
+        );
+    }
+
+    let encoded = base64::encode(&part.data);
+    let mut lines = vec![];
+    for chunk in encoded.as_bytes().chunks(76) {
+        lines.push(String::from_utf8_lossy(chunk).to_string());
+    }
+
+    format!(
+        "Content-Type: {}; name=\"{}\"\r\n\
+         Content-Disposition: attachment; filename=\"{}\"\r\n\
+         Content-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+        part.content_type,
+        name,
+        name,
+        lines.join("\r\n")
+    )
+}
+
+fn mixed_part(first: &str, attachments: &[Part]) -> String {
+    let boundary = boundary();
+    let mut result = format!(
+        "Content-Type: multipart/mixed; boundary=\"{b}\"\r\n\r\n--{b}\r\n{first}",
+        b = boundary,
+        first = first
+    );
+    for part in attachments {
+        result.push_str(&format!("\r\n--{}\r\n{}", boundary, attachment_part(part)));
+    }
+    result.push_str(&format!("\r\n--{}--\r\n", boundary));
+
+    result
+}
+
+// RFC 3156 multipart/signed
+fn signed_part(part: &str, key: &str) -> Result<String, failure::Error> {
+    let signature = pgp::sign(part.as_bytes(), key)?;
+    let boundary = boundary();
+
+    Ok(format!(
+        "Content-Type: multipart/signed; micalg=pgp-sha256;\r\n \
+         protocol=\"application/pgp-signature\"; boundary=\"{b}\"\r\n\r\n\
+         --{b}\r\n{part}\r\n--{b}\r\n\
+         Content-Type: application/pgp-signature; name=\"signature.asc\"\r\n\
+         Content-Description: OpenPGP digital signature\r\n\r\n\
+         {sig}\r\n--{b}--\r\n",
+        b = boundary,
+        part = part,
+        sig = String::from_utf8_lossy(&signature).replace('\n', "\r\n"),
+    ))
+}
+
+// RFC 3156 multipart/encrypted
+fn encrypted_part(
+    part: &str,
+    recipients: &[String],
+    autocrypt: &autocrypt::Store,
+    sign_key: Option<&str>,
+    tmp_dir: &Path,
+) -> Result<String, failure::Error> {
+    // recipients only known through autocrypt are passed as key files
+    let (keyring, others): (Vec<String>, Vec<String>) =
+        recipients.iter().cloned().partition(|r| pgp::has_key(r));
+    let mut key_files = vec![];
+    for (i, r) in others.iter().enumerate() {
+        let key = autocrypt
+            .key(r)
+            .ok_or(failure::format_err!("No encryption key for {}", r))?;
+        let path = tmp_dir.join(format!("autocrypt-{}.gpg", i));
+        std::fs::write(&path, key)?;
+        key_files.push(path);
+    }
+
+    let cipher = pgp::encrypt(part.as_bytes(), &keyring, &key_files, sign_key);
+    for path in &key_files {
+        let _ = std::fs::remove_file(path);
+    }
+    let cipher = cipher?;
+    let boundary = boundary();
+
+    Ok(format!(
+        "Content-Type: multipart/encrypted;\r\n \
+         protocol=\"application/pgp-encrypted\"; boundary=\"{b}\"\r\n\r\n\
+         --{b}\r\n\
+         Content-Type: application/pgp-encrypted\r\n\
+         Content-Description: PGP/MIME version identification\r\n\r\n\
+         Version: 1\r\n\r\n--{b}\r\n\
+         Content-Type: application/octet-stream; name=\"encrypted.asc\"\r\n\
+         Content-Description: OpenPGP encrypted message\r\n\
+         Content-Disposition: inline; filename=\"encrypted.asc\"\r\n\r\n\
+         {cipher}\r\n--{b}--\r\n",
+        b = boundary,
+        cipher = String::from_utf8_lossy(&cipher).replace('\n', "\r\n"),
+    ))
+}
+
+// recipients without key in gpg keyring or autocrypt store
+pub fn missing_keys(draft: &Draft, autocrypt: &autocrypt::Store) -> Vec<String> {
+    pgp::missing_keys(&draft.recipients())
+        .into_iter()
+        .filter(|r| autocrypt.key(r).is_none())
+        .collect()
+}
+
+pub fn build(
+    draft: &Draft,
+    config: &Config,
+    autocrypt: &autocrypt::Store,
+    tmp_dir: &Path,
+) -> Result<Vec<u8>, failure::Error> {
+    debug!("compose::build");
+
+    let sender = draft.sender();
+    let identity = config.identity(&sender);
+    let key = identity
+        .map(|id| id.key().to_string())
+        .unwrap_or_else(|| sender.to_string());
+
+    let part = match &draft.calendar {
+        Some(calendar) => alternative_part(&draft.body, config.format_flowed, calendar),
+        None => text_part(&draft.body, config.format_flowed),
+    };
+    let part = match draft.attachments.is_empty() {
+        true => part,
+        _ => mixed_part(&part, &draft.attachments),
+    };
+    let content = match (draft.sign, draft.encrypt) {
+        (_, true) => {
+            let mut recipients = draft.recipients();
+            // keep sent copy readable
+            recipients.push(key.to_string());
+            let sign_key = if draft.sign { Some(key.as_str()) } else { None };
+            encrypted_part(&part, &recipients, autocrypt, sign_key, tmp_dir)?
+        }
+        (true, false) => signed_part(&part, &key)?,
+        _ => part,
+    };
+
+    let mut headers = vec![
+        ("From", encode_addresses(&draft.from)),
+        ("To", encode_addresses(&draft.to)),
+    ];
+    if !draft.cc.is_empty() {
+        headers.push(("Cc", encode_addresses(&draft.cc)));
+    }
+    headers.push(("Subject", encode_header(&draft.subject)));
+    headers.push(("Date", chrono::Local::now().to_rfc2822()));
+    headers.push(("Message-ID", message_id(&sender)));
+    headers.push(("MIME-Version", "1.0".to_string()));
+    // missing key leaves the header out instead of failing the send
+    if let Some(identity) = identity.filter(|id| id.autocrypt) {
+        match pgp::export(&key) {
+            Ok(public_key) if !public_key.is_empty() => headers.push((
+                "Autocrypt",
+                autocrypt::make_header(&sender, identity.encrypt, &public_key),
+            )),
+            Ok(_) => warn!("compose::build: no public key for {}", key),
+            Err(e) => warn!("compose::build: exporting key failed: {}", e),
+        }
+    }
+
+    let mut message = String::new();
+    for (name, value) in headers {
+        message.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    message.push_str(&content);
+
+    Ok(message.into_bytes())
+}
+
+pub fn send(data: &[u8], config: &Config) -> Result<(), failure::Error> {
+    debug!("compose::send");
+
+    if let Some(sendmail) = &config.sendmail {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(sendmail)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // written from a thread so a chatty command can't block the input
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or(failure::format_err!("Failed to run '{}'", sendmail))?;
+        let message = data.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&message));
+
+        let output = child.wait_with_output()?;
+        let written = writer
+            .join()
+            .map_err(|_| failure::format_err!("Failed to write to '{}'", sendmail))?;
+        if !output.status.success() {
+            bail!(
+                "'{}' failed: {}",
+                sendmail,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        written?;
+    }
+
+    crate::notmuch::insert_message(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // text of encoded-words, for checking round trips
+    fn decode_words(value: &str) -> String {
+        let mut bytes = vec![];
+        for word in value.split_whitespace() {
+            let text = &word["=?utf-8?Q?".len()..word.len() - 2];
+            let mut chars = text.bytes();
+            while let Some(b) = chars.next() {
+                match b {
+                    b'_' => bytes.push(b' '),
+                    b'=' => {
+                        let hex: String = chars.by_ref().take(2).map(|b| b as char).collect();
+                        bytes.push(u8::from_str_radix(&hex, 16).unwrap());
+                    }
+                    _ => bytes.push(b),
+                }
+            }
+        }
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn ascii_header_is_kept() {
+        assert_eq!(encode_header("Hello, world"), "Hello, world");
+        assert_eq!(encode_header(""), "");
+    }
+
+    #[test]
+    fn header_is_encoded_in_short_words() {
+        let subject = "Äänestys: ".repeat(10) + "🎉";
+        let encoded = encode_header(&subject);
+        for word in encoded.split("\r\n ") {
+            assert!(word.len() <= 75, "{}", word);
+            assert!(word.starts_with("=?utf-8?Q?") && word.ends_with("?="));
+        }
+        assert!(encoded.contains("\r\n "));
+        assert_eq!(decode_words(&encoded), subject);
+    }
+
+    #[test]
+    fn encoded_words_use_phrase_characters() {
+        let encoded = encode_header("ä,b.c?d=e_f");
+        assert_eq!(encoded, "=?utf-8?Q?=C3=A4=2Cb=2Ec=3Fd=3De=5Ff?=");
+    }
+
+    #[test]
+    fn only_display_names_are_encoded() {
+        let encoded = encode_addresses("Jörg Müller <jorg@example.com>, plain@example.com");
+        assert_eq!(
+            encoded,
+            "=?utf-8?Q?J=C3=B6rg_M=C3=BCller?= <jorg@example.com>, plain@example.com"
+        );
+        let quoted = encode_addresses("\"Öberg, Ann\" <ann@example.com>, Bob <bob@example.com>");
+        assert_eq!(
+            quoted,
+            "=?utf-8?Q?=C3=96berg=2C_Ann?= <ann@example.com>, Bob <bob@example.com>"
+        );
+        assert_eq!(encode_addresses(""), "");
+    }
+
+    #[test]
+    fn addresses_are_extracted() {
+        assert_eq!(
+            addresses("\"Doe, John\" <john@example.com>, jane@example.com, nobody"),
+            vec!["john@example.com", "jane@example.com"]
+        );
+        assert!(addresses("").is_empty());
+    }
+
+    #[test]
+    fn quoted_printable_encodes_trailing_space_and_from() {
+        let encoded = quoted_printable("From here \nä=1");
+        assert_eq!(encoded, "=46rom here=20\r\n=C3=A4=3D1\r\n");
+    }
+
+    #[test]
+    fn quoted_printable_lines_are_short() {
+        let encoded = quoted_printable(&"ö".repeat(100));
+        assert!(encoded.lines().all(|line| line.len() <= 76));
+    }
+}
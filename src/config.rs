@@ -0,0 +1,211 @@
+use crate::format;
+use log::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub address: String,
+    // gpg key id used for signing, defaults to address
+    pub key: Option<String>,
+    #[serde(default)]
+    pub sign: bool,
+    #[serde(default)]
+    pub encrypt: bool,
+    // send own key in Autocrypt header
+    #[serde(default = "default_true")]
+    pub autocrypt: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Identity {
+    pub fn mailbox(&self) -> String {
+        format!("{} <{}>", self.name, self.address)
+    }
+
+    pub fn key(&self) -> &str {
+        self.key.as_ref().unwrap_or(&self.address)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sort {
+    NewestFirst,
+    OldestFirst,
+}
+
+impl Sort {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sort::NewestFirst => "newest-first",
+            Sort::OldestFirst => "oldest-first",
+        }
+    }
+
+    pub fn toggle(self) -> Sort {
+        match self {
+            Sort::NewestFirst => Sort::OldestFirst,
+            Sort::OldestFirst => Sort::NewestFirst,
+        }
+    }
+}
+
+fn default_sort() -> Sort {
+    Sort::NewestFirst
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Search {
+    pub name: String,
+    pub query: String,
+    #[serde(default = "default_sort")]
+    pub sort: Sort,
+}
+
+// how a tag is shown, e.g.
+// [tags.unread]
+// symbol = "●"
+// style = "yellow bold"
+// hide = ["index"]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TagRule {
+    pub symbol: Option<String>,
+    // colors and modifiers separated by spaces, "on <color>" for background
+    pub style: Option<String>,
+    // views the tag is left out from: "index" and "thread"
+    pub hide: Vec<String>,
+}
+
+impl TagRule {
+    fn new(symbol: Option<&str>, style: Option<&str>, hide: &[&str]) -> Self {
+        TagRule {
+            symbol: symbol.map(|s| s.to_string()),
+            style: style.map(|s| s.to_string()),
+            hide: hide.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub editor: String,
+    // command the composed message is piped to, e.g. "msmtp -t"
+    // when not set messages are only inserted to notmuch
+    pub sendmail: Option<String>,
+    pub identities: Vec<Identity>,
+    // external html renderer, '%w' is replaced with view width
+    // e.g. "w3m -T text/html -dump -cols %w"
+    pub html_renderer: Option<String>,
+    // send text as format=flowed so it can be reflowed by the reader
+    pub format_flowed: bool,
+    // command links are opened with
+    pub opener: String,
+    // command links are piped to when copied
+    pub clipboard: String,
+    // default directory for saved attachments
+    pub attachment_dir: Option<String>,
+    // keep opened attachments after exit, for viewers that fork
+    pub keep_temp_files: bool,
+    // click and scroll with the mouse, off leaves selection to the terminal
+    pub mouse: bool,
+    // index row, see format.rs for the directives
+    pub index_format: String,
+    // saved searches selected with keys 1-9, the first one is shown on start
+    pub searches: Vec<Search>,
+    // tag display rules by tag name, raw tags are shown in message headers
+    pub tags: HashMap<String, TagRule>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            editor: "nvim".to_string(),
+            sendmail: None,
+            identities: vec![Identity {
+                name: "Me".to_string(),
+                address: "me@localhost".to_string(),
+                key: None,
+                sign: false,
+                encrypt: false,
+                autocrypt: true,
+            }],
+            html_renderer: None,
+            format_flowed: false,
+            opener: "xdg-open".to_string(),
+            clipboard: "xclip -selection clipboard".to_string(),
+            attachment_dir: None,
+            keep_temp_files: false,
+            mouse: true,
+            index_format: "%-12d  %-20.20f  %a %s  %g".to_string(),
+            searches: vec![Search {
+                name: "inbox".to_string(),
+                query: "tag:inbox".to_string(),
+                sort: Sort::NewestFirst,
+            }],
+            tags: vec![
+                (
+                    "unread",
+                    TagRule::new(Some("\u{25cf}"), Some("yellow bold"), &[]),
+                ),
+                ("attachment", TagRule::new(Some("\u{1f4ce}"), None, &[])),
+                ("replied", TagRule::new(Some("\u{21a9}"), None, &[])),
+                ("flagged", TagRule::new(Some("\u{2691}"), Some("red"), &[])),
+                ("inbox", TagRule::new(None, None, &["index", "thread"])),
+                ("signed", TagRule::new(None, None, &["index"])),
+            ]
+            .into_iter()
+            .map(|(tag, rule)| (tag.to_string(), rule))
+            .collect(),
+        }
+    }
+}
+
+impl Config {
+    // identity matching address, first one if no match
+    pub fn identity(&self, address: &str) -> Option<&Identity> {
+        self.identities
+            .iter()
+            .find(|id| id.address.eq_ignore_ascii_case(address))
+            .or_else(|| self.identities.first())
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("nutt"))
+}
+
+pub fn load() -> Result<Config, failure::Error> {
+    let path = match config_dir() {
+        Some(dir) => dir.join("config.toml"),
+        None => return Ok(Config::default()),
+    };
+    debug!("config::load: {:?}", &path);
+
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let data = std::fs::read_to_string(&path)?;
+    let mut config: Config =
+        toml::from_str(&data).map_err(|e| failure::format_err!("{}: {}", path.display(), e))?;
+
+    format::check(&config.index_format)
+        .map_err(|e| failure::format_err!("{}: index_format: {}", path.display(), e))?;
+
+    if config.identities.is_empty() {
+        config.identities = Config::default().identities;
+    }
+    if config.searches.is_empty() {
+        config.searches = Config::default().searches;
+    }
+
+    Ok(config)
+}
@@ -0,0 +1,260 @@
+// unified diff detection for patch emails and attachments
+use crate::width;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    File,
+    Hunk,
+    Add,
+    Remove,
+    Context,
+}
+
+fn is_file_header(line: &str) -> bool {
+    [
+        "diff ",
+        "index ",
+        "--- ",
+        "+++ ",
+        "new file mode",
+        "deleted file mode",
+        "old mode",
+        "new mode",
+        "similarity index",
+        "rename from",
+        "rename to",
+        "Binary files",
+    ]
+    .iter()
+    .any(|prefix| line.starts_with(prefix))
+}
+
+// line counts of '@@ -a,b +c,d @@', missing count means 1
+fn hunk_counts(line: &str) -> Option<(usize, usize)> {
+    let mut parts = line.split_whitespace().skip(1);
+    let count = |range: &str| -> Option<usize> {
+        match range[1..].find(',') {
+            Some(pos) => range[pos + 2..].parse().ok(),
+            None => Some(1),
+        }
+    };
+    let old = count(parts.next().filter(|p| p.starts_with('-'))?)?;
+    let new = count(parts.next().filter(|p| p.starts_with('+'))?)?;
+    Some((old, new))
+}
+
+// kind of each line, None outside of diffs
+pub fn classify(lines: &[&str]) -> Vec<Option<Kind>> {
+    let mut result = vec![];
+    // after a file header until a line that isn't part of the diff
+    let mut in_diff = false;
+    // old and new lines left in current hunk
+    let mut remaining: (usize, usize) = (0, 0);
+
+    for (i, line) in lines.iter().enumerate() {
+        let kind = if remaining != (0, 0) {
+            match line.chars().next() {
+                Some('+') => {
+                    remaining.1 = remaining.1.saturating_sub(1);
+                    Kind::Add
+                }
+                Some('-') => {
+                    remaining.0 = remaining.0.saturating_sub(1);
+                    Kind::Remove
+                }
+                // '\ No newline at end of file'
+                Some('\\') => Kind::Context,
+                _ => {
+                    remaining = (remaining.0.saturating_sub(1), remaining.1.saturating_sub(1));
+                    Kind::Context
+                }
+            }
+        } else if line.starts_with("diff --git ")
+            || (line.starts_with("--- ")
+                && lines.get(i + 1).map_or(false, |l| l.starts_with("+++ ")))
+        {
+            in_diff = true;
+            Kind::File
+        } else if in_diff && line.starts_with("@@ ") {
+            remaining = hunk_counts(line).unwrap_or((0, 0));
+            Kind::Hunk
+        } else if in_diff && is_file_header(line) {
+            Kind::File
+        } else if in_diff && line.starts_with('\\') {
+            // no newline marker after the last line of a hunk
+            Kind::Context
+        } else {
+            in_diff = false;
+            result.push(None);
+            continue;
+        };
+
+        result.push(Some(kind));
+    }
+
+    result
+}
+
+// ' path | n +-' lines and a summary like git's
+pub fn diffstat(lines: &[&str], kinds: &[Option<Kind>]) -> Vec<String> {
+    let mut files: Vec<(String, usize, usize)> = vec![];
+
+    for (line, kind) in lines.iter().zip(kinds.iter()) {
+        match kind {
+            Some(Kind::File) if line.starts_with("+++ ") || line.starts_with("--- ") => {
+                let path = line[4..].split('\t').next().unwrap_or("").trim();
+                if path == "/dev/null" {
+                    continue;
+                }
+                let path = match path.get(..2) {
+                    Some("a/") | Some("b/") => &path[2..],
+                    _ => path,
+                };
+                // '+++' repeats the '---' path
+                if files
+                    .last()
+                    .map_or(true, |(p, a, r)| p != path || *a + *r > 0)
+                {
+                    files.push((path.to_string(), 0, 0));
+                }
+            }
+            Some(Kind::Add) => {
+                if let Some(file) = files.last_mut() {
+                    file.1 += 1;
+                }
+            }
+            Some(Kind::Remove) => {
+                if let Some(file) = files.last_mut() {
+                    file.2 += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    // rename or mode only changes have both paths
+    files.dedup_by(|b, a| a.0 == b.0 && b.1 + b.2 == 0);
+
+    let path_width = files.iter().map(|f| width::width(&f.0)).max().unwrap_or(0);
+    let most = files.iter().map(|f| f.1 + f.2).max().unwrap_or(0);
+    let mut result = vec![];
+    for (path, added, removed) in &files {
+        // scale bars to 40 columns like git does
+        let scale = |n: usize| match most > 40 {
+            true => (n * 40 + most - 1) / most,
+            _ => n,
+        };
+        result.push(format!(
+            " {} | {:>4} {}{}",
+            width::pad(path, path_width),
+            added + removed,
+            "+".repeat(scale(*added)),
+            "-".repeat(scale(*removed))
+        ));
+    }
+    result.push(format!(
+        " {} file(s) changed, {} insertions(+), {} deletions(-)",
+        files.len(),
+        files.iter().map(|f| f.1).sum::<usize>(),
+        files.iter().map(|f| f.2).sum::<usize>()
+    ));
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "Fix the thing\n\
+---\n\
+diff --git a/src/ä.rs b/src/ä.rs\n\
+index 1111111..2222222 100644\n\
+--- a/src/ä.rs\n\
++++ b/src/ä.rs\n\
+@@ -1,3 +1,3 @@ fn main\n\
+ keep\n\
+-old\n\
++new\n\
+ keep\n\
+\\ No newline at end of file\n\
+diff --git a/b.txt b/b.txt\n\
+new file mode 100644\n\
+--- /dev/null\n\
++++ b/b.txt\n\
+@@ -0,0 +1 @@\n\
++--- not a header\n\
+-- \n\
+signature";
+
+    #[test]
+    fn diff_lines_are_classified() {
+        let lines: Vec<&str> = PATCH.lines().collect();
+        let kinds = classify(&lines);
+        use Kind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                None,
+                None,
+                Some(File),
+                Some(File),
+                Some(File),
+                Some(File),
+                Some(Hunk),
+                Some(Context),
+                Some(Remove),
+                Some(Add),
+                Some(Context),
+                Some(Context),
+                Some(File),
+                Some(File),
+                Some(File),
+                Some(File),
+                Some(Hunk),
+                Some(Add),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_text_has_no_diff() {
+        let lines = vec!["--- not a diff", "text", "@@ -1 +1 @@", "+1"];
+        assert!(classify(&lines).iter().all(|k| k.is_none()));
+        assert!(classify(&[]).is_empty());
+    }
+
+    #[test]
+    fn broken_hunk_headers_are_safe() {
+        let lines = vec!["--- a", "+++ b", "@@ -1,x +1 @@", "@@", "@@ - + @@", "+a"];
+        let kinds = classify(&lines);
+        assert_eq!(kinds[2], Some(Kind::Hunk));
+        assert_eq!(hunk_counts("@@ -3 +4,2 @@"), Some((1, 2)));
+        assert_eq!(hunk_counts("@@ -1,x +1 @@"), None);
+        assert_eq!(hunk_counts("@@"), None);
+    }
+
+    #[test]
+    fn diffstat_counts_files() {
+        let lines: Vec<&str> = PATCH.lines().collect();
+        let stat = diffstat(&lines, &classify(&lines));
+        assert_eq!(
+            stat,
+            vec![
+                " src/ä.rs |    2 +-",
+                " b.txt    |    1 +",
+                " 2 file(s) changed, 2 insertions(+), 1 deletions(-)",
+            ]
+        );
+    }
+
+    #[test]
+    fn diffstat_bars_are_scaled() {
+        let mut text = String::from("--- a/x\n+++ b/x\n@@ -0,0 +1,80 @@\n");
+        text.push_str(&"+line\n".repeat(80));
+        let lines: Vec<&str> = text.lines().collect();
+        let stat = diffstat(&lines, &classify(&lines));
+        assert_eq!(stat[0], format!(" x |   80 {}", "+".repeat(40)));
+    }
+}
@@ -0,0 +1,313 @@
+// RFC 3676 format=flowed text
+use crate::quote;
+use std::collections::HashMap;
+
+// line width of generated text, as recommended by the rfc
+const WIDTH: usize = 72;
+
+// quote depth and content with space stuffing removed
+fn split_quote(line: &str) -> (usize, &str) {
+    let depth = line.chars().take_while(|c| *c == '>').count();
+    let rest = &line[depth..];
+    (depth, rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+fn quote_prefix(depth: usize, text: &str) -> String {
+    match (depth, text.is_empty()) {
+        (0, _) => String::new(),
+        (_, true) => ">".repeat(depth),
+        _ => format!("{} ", ">".repeat(depth)),
+    }
+}
+
+// one line per paragraph, reflowing to width is left to the view
+pub fn decode(text: &str, delsp: bool) -> String {
+    let mut result = String::new();
+    // quote depth and joined text of current paragraph
+    let mut paragraph: Option<(usize, String)> = None;
+    let flush = |result: &mut String, paragraph: &mut Option<(usize, String)>| {
+        if let Some((depth, text)) = paragraph.take() {
+            result.push_str(&quote_prefix(depth, &text));
+            result.push_str(&text);
+            result.push('\n');
+        }
+    };
+
+    for line in text.lines() {
+        let (depth, content) = split_quote(line.trim_end_matches('\r'));
+        // quote depth change ends paragraph even after a soft break
+        if paragraph.as_ref().map_or(false, |(d, _)| *d != depth) {
+            flush(&mut result, &mut paragraph);
+        }
+
+        let flowed = content.ends_with(' ') && content != "-- ";
+        let content = match flowed && delsp {
+            true => &content[..content.len() - 1],
+            _ => content,
+        };
+        paragraph
+            .get_or_insert_with(|| (depth, String::new()))
+            .1
+            .push_str(content);
+
+        if !flowed {
+            flush(&mut result, &mut paragraph);
+        }
+    }
+    flush(&mut result, &mut paragraph);
+
+    result
+}
+
+// content split after spaces to lines of at most width, long words kept whole
+fn soft_breaks(content: &str, width: usize) -> Vec<&str> {
+    let mut result = vec![];
+    let mut rest = content;
+
+    while rest.chars().count() > width {
+        let limit = rest
+            .char_indices()
+            .nth(width)
+            .map_or(rest.len(), |(i, _)| i);
+        let pos = match rest[..limit].rfind(' ') {
+            Some(pos) if pos > 0 => pos,
+            _ => match rest[limit..].find(' ') {
+                Some(pos) => limit + pos,
+                None => break,
+            },
+        };
+        result.push(&rest[..=pos]);
+        rest = &rest[pos + 1..];
+    }
+    result.push(rest);
+
+    result
+}
+
+// text as flowed lines, trailing space marks a soft break
+pub fn encode(text: &str) -> String {
+    let mut result = String::new();
+
+    for line in text.lines() {
+        let depth = quote::depth(line);
+        let content = match depth {
+            0 => line,
+            _ => line.trim_start_matches(|c| c == '>' || c == ' ' || c == '\t'),
+        };
+        // trailing spaces of hard breaks would make them soft
+        let content = match content {
+            "-- " => content,
+            _ => content.trim_end(),
+        };
+        let prefix = quote_prefix(depth, content);
+
+        for part in soft_breaks(content, WIDTH.saturating_sub(prefix.len()).max(20)) {
+            result.push_str(&prefix);
+            // space stuffing of lines that would be read as quoted or mangled
+            if depth == 0 && (part.starts_with(' ') || part.starts_with("From ")) {
+                result.push(' ');
+            }
+            result.push_str(part);
+            result.push('\n');
+        }
+    }
+
+    result
+}
+
+// unfolded value of header, case insensitive name
+fn header(headers: &str, name: &str) -> Option<String> {
+    let mut value: Option<String> = None;
+
+    for line in headers.lines() {
+        match (line.starts_with(' ') || line.starts_with('\t'), &mut value) {
+            (true, Some(value)) => value.push_str(line),
+            (true, None) => {}
+            (false, Some(_)) => break,
+            (false, None) => {
+                if let Some(pos) = line.find(':') {
+                    if line[..pos].trim().eq_ignore_ascii_case(name) {
+                        value = Some(line[pos + 1..].trim().to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    value
+}
+
+fn param<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    value.split(';').skip(1).find_map(|param| {
+        let pos = param.find('=')?;
+        match param[..pos].trim().eq_ignore_ascii_case(name) {
+            true => Some(param[pos + 1..].trim().trim_matches('"')),
+            _ => None,
+        }
+    })
+}
+
+// bodies between boundary lines
+fn split_parts(body: &str, boundary: &str) -> Vec<String> {
+    let delimiter = format!("--{}", boundary);
+    let mut result = vec![];
+    let mut part: Option<String> = None;
+
+    for line in body.lines() {
+        let trimmed = line.trim_end();
+        if trimmed.starts_with(&delimiter) {
+            let rest = &trimmed[delimiter.len()..];
+            if rest.is_empty() || rest == "--" {
+                result.extend(part.take());
+                match rest {
+                    "--" => break,
+                    _ => part = Some(String::new()),
+                }
+                continue;
+            }
+        }
+        if let Some(part) = &mut part {
+            part.push_str(line);
+            part.push('\n');
+        }
+    }
+
+    result
+}
+
+// numbers parts depth first like notmuch part ids
+fn walk(entity: &str, id: &mut usize, result: &mut HashMap<usize, bool>) {
+    *id += 1;
+    let (headers, body) = match entity.find("\n\n") {
+        // part without headers
+        _ if entity.starts_with('\n') => ("", &entity[1..]),
+        Some(pos) => (&entity[..pos], &entity[pos + 2..]),
+        None => (entity, ""),
+    };
+    let content_type = header(headers, "content-type").unwrap_or_default();
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+
+    if mime == "text/plain" {
+        if param(&content_type, "format").map_or(false, |f| f.eq_ignore_ascii_case("flowed")) {
+            let delsp =
+                param(&content_type, "delsp").map_or(false, |d| d.eq_ignore_ascii_case("yes"));
+            result.insert(*id, delsp);
+        }
+    } else if mime.starts_with("multipart/") {
+        if let Some(boundary) = param(&content_type, "boundary") {
+            for part in split_parts(body, boundary) {
+                walk(&part, id, result);
+            }
+        }
+    } else if mime == "message/rfc822" {
+        walk(body, id, result);
+    }
+}
+
+// ids of flowed text/plain parts with their DelSp, notmuch json
+// leaves out content type parameters so raw headers are read
+pub fn parts(raw: &str) -> HashMap<usize, bool> {
+    let mut result = HashMap::new();
+    walk(&raw.replace("\r\n", "\n"), &mut 0, &mut result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_breaks_are_joined() {
+        assert_eq!(
+            decode("hyvää \nhuomenta\nnext\n", false),
+            "hyvää huomenta\nnext\n"
+        );
+        assert_eq!(decode("split \nword\n", true), "splitword\n");
+        assert_eq!(decode("", false), "");
+    }
+
+    #[test]
+    fn quotes_and_signature() {
+        assert_eq!(decode("> a \n> b\n>> c\n", false), "> a b\n>> c\n");
+        // quote depth change ends paragraph
+        assert_eq!(decode("> a \nb\n", false), "> a \nb\n");
+        assert_eq!(decode("text\n-- \nsig\n", false), "text\n-- \nsig\n");
+        assert_eq!(decode(">\n", false), ">\n");
+    }
+
+    #[test]
+    fn space_stuffing_is_removed() {
+        assert_eq!(decode(" From me\n", false), "From me\n");
+        assert_eq!(encode("From me\n"), " From me\n");
+        assert_eq!(encode(" indented\n"), "  indented\n");
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let text = format!(
+            "{}\n> quoted line\n-- \nsig\n",
+            "sana ".repeat(40).trim_end()
+        );
+        let encoded = encode(&text);
+        assert!(encoded.lines().all(|line| line.chars().count() <= WIDTH));
+        assert_eq!(decode(&encoded, false), text);
+    }
+
+    #[test]
+    fn long_words_are_kept_whole() {
+        let word = "ä".repeat(100);
+        assert_eq!(soft_breaks(&word, 72), vec![word.as_str()]);
+        assert_eq!(soft_breaks("", 72), vec![""]);
+        assert_eq!(soft_breaks("a b", 1), vec!["a ", "b"]);
+    }
+
+    const RAW: &str = "From: a@example.com\r
+Content-Type: multipart/mixed; boundary=\"outer\"\r
+\r
+--outer\r
+Content-Type: multipart/alternative;\r
+ boundary=inner\r
+\r
+--inner\r
+Content-Type: text/plain; charset=utf-8; format=flowed; delsp=yes\r
+\r
+soft \r
+--inner\r
+Content-Type: text/html\r
+\r
+<p>x</p>\r
+--inner--\r
+--outer\r
+Content-Type: message/rfc822\r
+\r
+Subject: forwarded\r
+Content-Type: text/plain; format=\"Flowed\"\r
+\r
+body\r
+--outer\r
+Content-Type: text/plain\r
+\r
+fixed\r
+--outer--\r
+";
+
+    #[test]
+    fn nested_parts_are_numbered_like_notmuch() {
+        let mut expected = HashMap::new();
+        expected.insert(3, true);
+        expected.insert(6, false);
+        assert_eq!(parts(RAW), expected);
+    }
+
+    #[test]
+    fn broken_messages_have_no_flowed_parts() {
+        assert!(parts("").is_empty());
+        assert!(parts("Content-Type: multipart/mixed\n\nno boundary\n").is_empty());
+        assert!(parts("Content-Type: text/plain; format=fixed\n\nx\n").is_empty());
+    }
+}
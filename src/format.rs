@@ -0,0 +1,303 @@
+// mutt style index row format
+//
+//   %d      relative date
+//   %{fmt}  date with strftime format, e.g. %{%Y-%m-%d}
+//   %f      from
+//   %s      subject, with thread tree
+//   %g      tags
+//   %a      attachment indicator
+//   %c      message size
+//   %n      number of messages in thread
+//   %%      literal '%'
+//
+// directives take optional '-' for left alignment, width and
+// '.max' for truncation, e.g. '%-20.20f', both in terminal columns
+
+use crate::width;
+use chrono::format::{Item, StrftimeItems};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Date(Option<String>),
+    From,
+    Subject,
+    Tags,
+    Attachment,
+    Size,
+    Count,
+}
+
+impl Field {
+    fn name(&self) -> &'static str {
+        match self {
+            Field::Date(_) => "Date",
+            Field::From => "From",
+            Field::Subject => "Subject",
+            Field::Tags => "Tags",
+            Field::Attachment => "A",
+            Field::Size => "Size",
+            Field::Count => "#",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Piece {
+    Text(String),
+    Field {
+        field: Field,
+        left: bool,
+        width: usize,
+        max: Option<usize>,
+    },
+}
+
+pub fn parse(format: &str) -> Vec<Piece> {
+    let mut result = vec![];
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            text.push(ch);
+            continue;
+        }
+
+        let left = chars.peek() == Some(&'-');
+        if left {
+            chars.next();
+        }
+        let mut width = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            width.push(*c);
+            chars.next();
+        }
+        let mut max = None;
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let mut digits = String::new();
+            while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                digits.push(*c);
+                chars.next();
+            }
+            max = digits.parse().ok();
+        }
+
+        let field = match chars.next() {
+            Some('d') => Field::Date(None),
+            Some('{') => Field::Date(Some((&mut chars).take_while(|c| *c != '}').collect())),
+            Some('f') => Field::From,
+            Some('s') => Field::Subject,
+            Some('g') => Field::Tags,
+            Some('a') => Field::Attachment,
+            Some('c') => Field::Size,
+            Some('n') => Field::Count,
+            Some('%') => {
+                text.push('%');
+                continue;
+            }
+            // unknown directives are kept as is
+            Some(c) => {
+                text.push('%');
+                text.push(c);
+                continue;
+            }
+            None => {
+                text.push('%');
+                continue;
+            }
+        };
+
+        if !text.is_empty() {
+            result.push(Piece::Text(text.to_string()));
+            text.clear();
+        }
+        result.push(Piece::Field {
+            field,
+            left,
+            width: width.parse().unwrap_or(0),
+            max,
+        });
+    }
+    if !text.is_empty() {
+        result.push(Piece::Text(text));
+    }
+
+    result
+}
+
+// formats chrono can't render would panic on every draw,
+// so they are rejected when the config is loaded
+pub fn check(format: &str) -> Result<(), failure::Error> {
+    for piece in parse(format) {
+        if let Piece::Field {
+            field: Field::Date(Some(date)),
+            ..
+        } = piece
+        {
+            if StrftimeItems::new(&date).any(|item| item == Item::Error) {
+                failure::bail!("invalid date format: %{{{}}}", date);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// pad and truncate a field made of styled parts
+fn pad<S: Copy>(
+    parts: Vec<(String, S)>,
+    default: S,
+    left: bool,
+    width: usize,
+    max: Option<usize>,
+) -> Vec<(String, S)> {
+    let mut result = vec![];
+    let mut len = 0;
+
+    for (text, style) in parts {
+        let text = match max {
+            Some(max) => width::truncate(&text, max.saturating_sub(len)).to_string(),
+            None => text,
+        };
+        len += width::width(&text);
+        if !text.is_empty() {
+            result.push((text, style));
+        }
+    }
+
+    if len < width {
+        let fill = (" ".repeat(width - len), default);
+        match left {
+            true => result.push(fill),
+            _ => result.insert(0, fill),
+        }
+    }
+
+    result
+}
+
+// row as styled parts, literal text gets the default style
+pub fn render_styled<S, F>(pieces: &[Piece], default: S, value: F) -> Vec<(String, S)>
+where
+    S: Copy,
+    F: Fn(&Field) -> Vec<(String, S)>,
+{
+    let mut result = vec![];
+
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => result.push((text.to_string(), default)),
+            Piece::Field {
+                field,
+                left,
+                width,
+                max,
+            } => result.extend(pad(value(field), default, *left, *width, *max)),
+        }
+    }
+
+    result
+}
+
+pub fn render<F>(pieces: &[Piece], value: F) -> String
+where
+    F: Fn(&Field) -> String,
+{
+    render_styled(pieces, (), |field| vec![(value(field), ())])
+        .into_iter()
+        .map(|(text, _)| text)
+        .collect()
+}
+
+// column titles aligned like the rows
+pub fn header(pieces: &[Piece]) -> String {
+    render(pieces, |field| field.name().to_string())
+}
+
+// binary units, the next one is used before a value would need four digits
+pub fn size(bytes: u64) -> String {
+    let kilos = bytes as f64 / 1024.0;
+    match bytes {
+        0..=1023 => format!("{}", bytes),
+        _ if kilos < 999.95 => format!("{:.1}K", kilos),
+        _ => format!("{:.1}M", kilos / 1024.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(field: &Field) -> String {
+        match field {
+            Field::Date(None) => "today".to_string(),
+            Field::Date(Some(fmt)) => format!("<{}>", fmt),
+            Field::From => "Jörg 张伟".to_string(),
+            Field::Subject => "Hello".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    #[test]
+    fn directives_are_parsed() {
+        let pieces = parse("%-12d %{%Y-%m-%d} %5.3f %% %x %");
+        let fields: Vec<Field> = pieces
+            .iter()
+            .filter_map(|piece| match piece {
+                Piece::Field { field, .. } => Some(field.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                Field::Date(None),
+                Field::Date(Some("%Y-%m-%d".to_string())),
+                Field::From
+            ]
+        );
+        match pieces.last() {
+            Some(Piece::Text(text)) => assert_eq!(text, " % %x %"),
+            _ => panic!("literal text expected"),
+        }
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn fields_are_padded_and_truncated_by_columns() {
+        assert_eq!(render(&parse("[%-8d]"), values), "[today   ]");
+        assert_eq!(render(&parse("[%8d]"), values), "[   today]");
+        // wide characters take two columns and are never split
+        assert_eq!(render(&parse("[%-8.8f]"), values), "[Jörg 张 ]");
+        assert_eq!(render(&parse("[%.6f]"), values), "[Jörg ]");
+        assert_eq!(render(&parse("%{%H}-%s"), values), "<%H>-Hello");
+    }
+
+    #[test]
+    fn header_is_aligned_with_rows() {
+        assert_eq!(header(&parse("%-6d|%3n|%s")), "Date  |  #|Subject");
+    }
+
+    #[test]
+    fn date_formats_are_checked() {
+        assert!(check("%-12d %{%Y-%m-%d %H:%M} %s").is_ok());
+        assert!(check("").is_ok());
+        assert!(check("%{%Q}").is_err());
+        assert!(check("%s %{%}").is_err());
+    }
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!(size(0), "0");
+        assert_eq!(size(999), "999");
+        assert_eq!(size(1000), "1000");
+        assert_eq!(size(1023), "1023");
+        assert_eq!(size(1024), "1.0K");
+        assert_eq!(size(2048), "2.0K");
+        assert_eq!(size(999 * 1024), "999.0K");
+        assert_eq!(size(1000 * 1024), "1.0M");
+        assert_eq!(size(1024 * 1024 - 1), "1.0M");
+        assert_eq!(size(3 * 1024 * 1024), "3.0M");
+    }
+}
@@ -0,0 +1,878 @@
+use crate::width;
+use log::*;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Text(String),
+    // name, attributes
+    Start(String, Vec<(String, String)>),
+    End(String),
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        "copy" => Some('©'),
+        "reg" => Some('®'),
+        "trade" => Some('™'),
+        "euro" => Some('€'),
+        "pound" => Some('£'),
+        "mdash" => Some('—'),
+        "ndash" => Some('–'),
+        "hellip" => Some('…'),
+        "lsquo" => Some('‘'),
+        "rsquo" => Some('’'),
+        "ldquo" => Some('“'),
+        "rdquo" => Some('”'),
+        "bull" => Some('•'),
+        "middot" => Some('·'),
+        "laquo" => Some('«'),
+        "raquo" => Some('»'),
+        "deg" => Some('°'),
+        "times" => Some('×'),
+        "auml" => Some('ä'),
+        "ouml" => Some('ö'),
+        "uuml" => Some('ü'),
+        "Auml" => Some('Ä'),
+        "Ouml" => Some('Ö'),
+        "Uuml" => Some('Ü'),
+        "aring" => Some('å'),
+        "Aring" => Some('Å'),
+        "szlig" => Some('ß'),
+        "eacute" => Some('é'),
+        "egrave" => Some('è'),
+        "aacute" => Some('á'),
+        "agrave" => Some('à'),
+        "ccedil" => Some('ç'),
+        "ntilde" => Some('ñ'),
+        "zwnj" | "zwj" | "shy" => Some('\u{200b}'),
+        _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+            u32::from_str_radix(&entity[2..], 16)
+                .ok()
+                .and_then(std::char::from_u32)
+                .map(printable)
+        }
+        _ if entity.starts_with('#') => entity[1..]
+            .parse::<u32>()
+            .ok()
+            .and_then(std::char::from_u32)
+            .map(printable),
+        _ => None,
+    }
+}
+
+// control characters would reach the terminal as escape sequences
+fn printable(ch: char) -> char {
+    match ch {
+        '\n' | '\t' => ch,
+        _ if ch.is_control() => '\u{fffd}',
+        _ => ch,
+    }
+}
+
+// text with entities decoded and raw control characters left out
+pub fn decode_entities(text: &str) -> String {
+    let mut result = String::new();
+    let text = text.replace(|c: char| c.is_control() && c != '\n' && c != '\t', "");
+    let mut rest = text.as_str();
+
+    while let Some(pos) = rest.find('&') {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..=end]).map(|ch| (ch, end + 2)));
+        match decoded {
+            Some((ch, len)) => {
+                if ch != '\u{200b}' {
+                    result.push(ch);
+                }
+                rest = &rest[len..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+fn parse_attributes(s: &str) -> Vec<(String, String)> {
+    let mut attrs = vec![];
+    let mut chars = s.chars().peekable();
+
+    loop {
+        while chars
+            .peek()
+            .map_or(false, |c| c.is_whitespace() || *c == '/')
+        {
+            chars.next();
+        }
+        let mut name = String::new();
+        while let Some(c) = chars.peek() {
+            if c.is_whitespace() || *c == '=' || *c == '/' {
+                break;
+            }
+            name.push(*c);
+            chars.next();
+        }
+        if name.is_empty() {
+            break;
+        }
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                chars.next();
+            }
+            match chars.peek() {
+                Some(&q) if q == '"' || q == '\'' => {
+                    chars.next();
+                    for c in &mut chars {
+                        if c == q {
+                            break;
+                        }
+                        value.push(c);
+                    }
+                }
+                _ => {
+                    while let Some(c) = chars.peek() {
+                        if c.is_whitespace() {
+                            break;
+                        }
+                        value.push(*c);
+                        chars.next();
+                    }
+                }
+            }
+        }
+        attrs.push((name.to_lowercase(), decode_entities(&value)));
+    }
+
+    attrs
+}
+
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let pos = match rest.find('<') {
+            Some(pos) => pos,
+            None => {
+                tokens.push(Token::Text(decode_entities(rest)));
+                break;
+            }
+        };
+        if pos > 0 {
+            tokens.push(Token::Text(decode_entities(&rest[..pos])));
+        }
+        rest = &rest[pos..];
+
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+        } else if rest.starts_with("</") {
+            let end = rest.find('>').unwrap_or(rest.len());
+            let name = rest[2..end].trim().to_lowercase();
+            tokens.push(Token::End(name));
+            rest = rest.get(end + 1..).unwrap_or("");
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let end = rest.find('>').unwrap_or(rest.len());
+            let tag = &rest[1..end];
+            let name_end = tag
+                .find(|c: char| c.is_whitespace() || c == '/')
+                .unwrap_or(tag.len());
+            let name = tag[..name_end].to_lowercase();
+            let attrs = parse_attributes(&tag[name_end..]);
+            rest = rest.get(end + 1..).unwrap_or("");
+
+            // raw text elements
+            if name == "script" || name == "style" {
+                let close = format!("</{}", name);
+                rest = rest
+                    .as_bytes()
+                    .windows(close.len())
+                    .position(|w| w.eq_ignore_ascii_case(close.as_bytes()))
+                    .map_or("", |end| &rest[end..]);
+            }
+            tokens.push(Token::Start(name, attrs));
+        } else {
+            tokens.push(Token::Text("<".to_string()));
+            rest = &rest[1..];
+        }
+    }
+
+    tokens
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+fn style(attrs: &[(String, String)]) -> String {
+    attr(attrs, "style")
+        .unwrap_or("")
+        .to_lowercase()
+        .replace(' ', "")
+}
+
+// elements not shown by browsers, often preview text or tracking
+fn is_hidden(attrs: &[(String, String)]) -> bool {
+    let style = style(attrs);
+    attr(attrs, "hidden").is_some()
+        || style.contains("display:none")
+        || style.contains("visibility:hidden")
+}
+
+// 1x1 images and hidden images are used for tracking
+fn is_tracking_pixel(attrs: &[(String, String)]) -> bool {
+    let tiny = |name| {
+        attr(attrs, name)
+            .map(|v| v.trim_end_matches("px").trim())
+            .map_or(false, |v| v == "0" || v == "1")
+    };
+    let style = style(attrs);
+
+    tiny("width")
+        || tiny("height")
+        || is_hidden(attrs)
+        || style.contains("width:1px")
+        || style.contains("height:1px")
+}
+
+// elements without end tags
+const VOID: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+// indexes of tables with at least two columns and no nested tables,
+// other tables are considered layout and rendered as blocks
+fn data_tables(tokens: &[Token]) -> HashSet<usize> {
+    let mut result = HashSet::new();
+    // start index, nested, max columns, current columns
+    let mut stack: Vec<(usize, bool, usize, usize)> = vec![];
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Start(name, _) if name == "table" => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.1 = true;
+                }
+                stack.push((i, false, 0, 0));
+            }
+            Token::Start(name, _) if name == "tr" => {
+                if let Some(table) = stack.last_mut() {
+                    table.3 = 0;
+                }
+            }
+            Token::Start(name, _) if name == "td" || name == "th" => {
+                if let Some(table) = stack.last_mut() {
+                    table.3 += 1;
+                    table.2 = std::cmp::max(table.2, table.3);
+                }
+            }
+            Token::End(name) if name == "table" => {
+                if let Some((start, nested, columns, _)) = stack.pop() {
+                    if !nested && columns > 1 {
+                        result.insert(start);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    result
+}
+
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        if !line.is_empty() && width::width(&line) + 1 + width::width(word) > width {
+            lines.push(line);
+            line = String::new();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+
+    // words wider than the column are split
+    lines
+        .iter()
+        .flat_map(|l| width::wrap(l, width, ""))
+        .collect()
+}
+
+struct Table {
+    rows: Vec<(Vec<String>, bool)>,
+    row: Vec<String>,
+    header: bool,
+    cell: Option<String>,
+}
+
+struct Renderer {
+    width: usize,
+    lines: Vec<String>,
+    line: String,
+    space: bool,
+    prefixes: Vec<&'static str>,
+    // None for unordered lists, item counter for ordered
+    lists: Vec<Option<usize>>,
+    pre: usize,
+    skip: usize,
+    href: Option<String>,
+    links: Vec<String>,
+    table: Option<Table>,
+}
+
+impl Renderer {
+    fn new(width: usize) -> Self {
+        Renderer {
+            width: std::cmp::max(width, 20),
+            lines: vec![],
+            line: String::new(),
+            space: false,
+            prefixes: vec![],
+            lists: vec![],
+            pre: 0,
+            skip: 0,
+            href: None,
+            links: vec![],
+            table: None,
+        }
+    }
+
+    fn prefix(&self) -> String {
+        self.prefixes.concat()
+    }
+
+    fn available(&self) -> usize {
+        std::cmp::max(self.width.saturating_sub(width::width(&self.prefix())), 10)
+    }
+
+    fn flush_line(&mut self) {
+        if !self.line.is_empty() {
+            let line = format!("{}{}", self.prefix(), self.line);
+            self.lines.push(line.trim_end().to_string());
+            self.line.clear();
+        }
+        self.space = false;
+    }
+
+    fn push_line(&mut self, line: &str) {
+        let line = format!("{}{}", self.prefix(), line);
+        self.lines.push(line.trim_end().to_string());
+    }
+
+    // paragraph break
+    fn block(&mut self) {
+        self.flush_line();
+        if self.lines.last().map_or(false, |l| {
+            !l.trim_start_matches(|c| c == '>' || c == ' ').is_empty()
+        }) {
+            self.push_line("");
+        }
+    }
+
+    fn word(&mut self, word: &str) {
+        if let Some(table) = &mut self.table {
+            if let Some(cell) = &mut table.cell {
+                if self.space && !cell.is_empty() {
+                    cell.push(' ');
+                }
+                cell.push_str(word);
+            }
+            self.space = false;
+            return;
+        }
+
+        let len = width::width(&self.line) + width::width(word);
+        if !self.line.is_empty() && len + 1 > self.available() {
+            self.flush_line();
+        }
+        if self.space && !self.line.is_empty() {
+            self.line.push(' ');
+        }
+        self.line.push_str(word);
+        self.space = false;
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.skip > 0 {
+            return;
+        }
+        if self.pre > 0 && self.table.is_none() {
+            for (i, part) in text.split('\n').enumerate() {
+                if i > 0 {
+                    let line = std::mem::take(&mut self.line);
+                    self.push_line(&line);
+                }
+                self.line.push_str(part);
+            }
+            return;
+        }
+
+        if text.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+        for word in text.split_whitespace() {
+            self.word(word);
+            self.space = true;
+        }
+        if !text.ends_with(char::is_whitespace) {
+            self.space = false;
+        }
+    }
+
+    fn start(&mut self, name: &str, attrs: &[(String, String)], data_table: bool) {
+        match name {
+            "head" | "title" | "script" | "style" => self.skip += 1,
+            "br" => match &mut self.table {
+                Some(_) => self.space = true,
+                None if self.line.is_empty() => self.push_line(""),
+                None => self.flush_line(),
+            },
+            "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "dl" | "dt" => {
+                match &self.table {
+                    Some(_) => self.space = true,
+                    None => self.block(),
+                }
+            }
+            "dd" => {
+                self.flush_line();
+                self.line.push_str("    ");
+            }
+            "hr" => {
+                self.block();
+                let rule = "-".repeat(std::cmp::min(self.available(), 40));
+                self.push_line(&rule);
+            }
+            "pre" => {
+                self.block();
+                self.pre += 1;
+            }
+            "blockquote" => {
+                self.block();
+                self.prefixes.push("> ");
+            }
+            "ul" | "ol" => {
+                if self.lists.is_empty() {
+                    self.block();
+                } else {
+                    self.flush_line();
+                }
+                self.lists.push(if name == "ol" { Some(0) } else { None });
+                self.prefixes.push("  ");
+            }
+            "li" => {
+                self.flush_line();
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", n)
+                    }
+                    _ => "* ".to_string(),
+                };
+                self.line.push_str(&marker);
+            }
+            "a" => {
+                self.href = attr(attrs, "href")
+                    .filter(|href| !href.starts_with('#') && !href.starts_with("javascript:"))
+                    .map(|href| href.trim().to_string());
+            }
+            "img" if !is_tracking_pixel(attrs) => {
+                if let Some(alt) = attr(attrs, "alt").filter(|alt| !alt.trim().is_empty()) {
+                    self.word(&format!("[{}]", alt.trim()));
+                }
+            }
+            "table" if data_table && self.table.is_none() => {
+                self.block();
+                self.table = Some(Table {
+                    rows: vec![],
+                    row: vec![],
+                    header: false,
+                    cell: None,
+                });
+            }
+            "td" | "th" => match &mut self.table {
+                Some(table) => {
+                    table.cell = Some(String::new());
+                    table.header |= name == "th";
+                    self.space = false;
+                }
+                None => self.space = true,
+            },
+            "tr" | "table" | "caption" if self.table.is_none() => self.flush_line(),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        match name {
+            "head" | "title" | "script" | "style" => self.skip = self.skip.saturating_sub(1),
+            "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "dl"
+                if self.table.is_none() =>
+            {
+                self.block()
+            }
+            "pre" => {
+                self.flush_line();
+                self.pre = self.pre.saturating_sub(1);
+                self.block();
+            }
+            "blockquote" => {
+                self.flush_line();
+                while self.lines.last().map_or(false, |l| l.trim_end() == ">") {
+                    self.lines.pop();
+                }
+                self.prefixes.pop();
+                self.block();
+            }
+            "ul" | "ol" => {
+                self.flush_line();
+                self.lists.pop();
+                self.prefixes.pop();
+                if self.lists.is_empty() {
+                    self.block();
+                }
+            }
+            "a" => {
+                if let Some(href) = self.href.take() {
+                    let n = match self.links.iter().position(|l| *l == href) {
+                        Some(pos) => pos + 1,
+                        None => {
+                            self.links.push(href);
+                            self.links.len()
+                        }
+                    };
+                    self.space = false;
+                    self.word(&format!("[{}]", n));
+                }
+            }
+            "td" | "th" => {
+                if let Some(table) = &mut self.table {
+                    if let Some(cell) = table.cell.take() {
+                        table.row.push(cell);
+                    }
+                }
+            }
+            "tr" => {
+                if let Some(table) = &mut self.table {
+                    if let Some(cell) = table.cell.take() {
+                        table.row.push(cell);
+                    }
+                    if !table.row.is_empty() {
+                        let row = std::mem::take(&mut table.row);
+                        table.rows.push((row, table.header));
+                    }
+                    table.header = false;
+                } else {
+                    self.flush_line();
+                }
+            }
+            "table" => match self.table.take() {
+                Some(mut table) => {
+                    if !table.row.is_empty() {
+                        table.rows.push((table.row, table.header));
+                    }
+                    self.render_table(&table.rows);
+                    self.block();
+                }
+                None => self.flush_line(),
+            },
+            _ => {}
+        }
+    }
+
+    fn render_table(&mut self, rows: &[(Vec<String>, bool)]) {
+        let columns = rows.iter().map(|(r, _)| r.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+
+        let mut widths = vec![0; columns];
+        for (row, _) in rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = std::cmp::max(widths[i], width::width(cell));
+            }
+        }
+
+        // shrink widest columns until the table fits
+        let available = self.available().saturating_sub(2 * (columns - 1));
+        while widths.iter().sum::<usize>() > available {
+            let (widest, width) = widths
+                .iter()
+                .cloned()
+                .enumerate()
+                .max_by_key(|(_, w)| *w)
+                .unwrap_or((0, 0));
+            if width <= 5 {
+                break;
+            }
+            widths[widest] = width - 1;
+        }
+
+        for (row, header) in rows {
+            let cells: Vec<Vec<String>> = (0..columns)
+                .map(|i| {
+                    wrap(
+                        row.get(i).map_or("", |c| c.as_str()),
+                        std::cmp::max(widths[i], 1),
+                    )
+                })
+                .collect();
+            let height = cells.iter().map(|c| c.len()).max().unwrap_or(1);
+            for n in 0..height {
+                let line = (0..columns)
+                    .map(|i| width::pad(cells[i].get(n).map_or("", |s| s.as_str()), widths[i]))
+                    .collect::<Vec<String>>()
+                    .join("  ");
+                self.push_line(&line);
+            }
+            if *header {
+                let rule = widths
+                    .iter()
+                    .map(|w| "-".repeat(*w))
+                    .collect::<Vec<String>>()
+                    .join("  ");
+                self.push_line(&rule);
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.block();
+
+        if !self.links.is_empty() {
+            self.prefixes.clear();
+            for (i, link) in self.links.clone().iter().enumerate() {
+                self.push_line(&format!("[{}] {}", i + 1, link));
+            }
+        }
+        while self.lines.last().map_or(false, |l| l.is_empty()) {
+            self.lines.pop();
+        }
+
+        let mut result = self.lines.join("\n");
+        result.push('\n');
+        result
+    }
+}
+
+pub fn render(html: &str, width: usize) -> String {
+    debug!("html::render: width: {}", width);
+
+    let tokens = tokenize(html);
+    let data_tables = data_tables(&tokens);
+    let mut renderer = Renderer::new(width);
+    // hidden element being left out, its name and nesting depth
+    let mut hidden: Option<(&str, usize)> = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match (token, &mut hidden) {
+            (Token::Start(name, _), Some((hidden_name, depth))) if name == hidden_name => {
+                *depth += 1
+            }
+            (Token::End(name), Some((hidden_name, depth))) if name == hidden_name => {
+                *depth -= 1;
+                if *depth == 0 {
+                    hidden = None;
+                }
+            }
+            (_, Some(_)) => {}
+            (Token::Start(name, attrs), None)
+                if is_hidden(attrs) && !VOID.contains(&name.as_str()) =>
+            {
+                hidden = Some((name, 1))
+            }
+            (Token::Text(text), None) => renderer.text(text),
+            (Token::Start(name, attrs), None) => {
+                renderer.start(name, attrs, data_tables.contains(&i))
+            }
+            (Token::End(name), None) => renderer.end(name),
+        }
+    }
+
+    renderer.finish()
+}
+
+// (href, anchor text) pairs
+pub fn anchors(html: &str) -> Vec<(String, String)> {
+    let mut result = vec![];
+    let mut anchor: Option<(String, String)> = None;
+
+    for token in tokenize(html) {
+        match (token, &mut anchor) {
+            (Token::Start(name, attrs), _) if name == "a" => {
+                anchor = attr(&attrs, "href")
+                    .filter(|href| !href.starts_with('#') && !href.starts_with("javascript:"))
+                    .map(|href| (href.trim().to_string(), String::new()));
+            }
+            (Token::Start(name, attrs), Some((_, text))) if name == "img" => {
+                text.push_str(attr(&attrs, "alt").unwrap_or(""));
+            }
+            (Token::Text(s), Some((_, text))) => text.push_str(&s),
+            (Token::End(name), _) if name == "a" => {
+                if let Some((href, text)) = anchor.take() {
+                    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+                    result.push((href, text));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let cases: &[(&str, &str, &str)] = &[
+            ("empty", "", "\n"),
+            ("paragraphs", "<p>one</p><p>two<br>three</p>", "one\n\ntwo\nthree\n"),
+            (
+                "nested lists",
+                "<ul><li>a<ol><li>b</li><li>c</li></ol></li><li>d</li></ul>",
+                "  * a\n    1. b\n    2. c\n  * d\n",
+            ),
+            (
+                "blockquote depth",
+                "<p>top</p><blockquote>one<blockquote>two</blockquote>back</blockquote>",
+                "top\n\n> one\n>\n> > two\n>\n> back\n",
+            ),
+            (
+                "data table",
+                "<table><tr><th>Nimi</th><th>Määrä</th></tr><tr><td>omena</td><td>2</td></tr></table>",
+                "Nimi   Määrä\n-----  -----\nomena  2\n",
+            ),
+            (
+                "layout table",
+                "<table><tr><td><table><tr><td>a</td></tr></table></td><td>b</td></tr></table>",
+                "a\nb\n",
+            ),
+            (
+                "single column table",
+                "<table><tr><td>only</td></tr><tr><td>cells</td></tr></table>",
+                "only\ncells\n",
+            ),
+            (
+                "link footnotes",
+                "<a href=\"https://a.example\">a</a> <a href=\"https://b.example\">b</a> \
+                 <a href=\"https://a.example\">again</a> <a href=\"#top\">top</a>",
+                "a[1] b[2] again[1] top\n\n[1] https://a.example\n[2] https://b.example\n",
+            ),
+            (
+                "tracking pixels",
+                "<p>text <img src=\"t.gif\" width=\"1\" height=\"1\" alt=\"pixel\">\
+                 <img src=\"x.png\" alt=\"logo\"></p>",
+                "text [logo]\n",
+            ),
+            (
+                "hidden elements",
+                "<div style=\"display: none\">preview <div>nested</div> text</div>\
+                 <span hidden>secret</span><p style=\"visibility:hidden\">x</p><p>shown</p>",
+                "shown\n",
+            ),
+            (
+                "scripts and head",
+                "<head><title>t</title><style>p { }</style></head><script>alert(1)</script>body",
+                "body\n",
+            ),
+            ("pre", "<pre>a  b\n  c</pre>", "a  b\n  c\n"),
+        ];
+        for (name, html, expected) in cases {
+            assert_eq!(render(html, 40), *expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn text_is_wrapped_to_width() {
+        let text = render(&format!("<p>{}</p>", "sana ".repeat(20)), 20);
+        assert!(text.lines().all(|line| width::width(line) <= 20));
+        assert_eq!(text.split_whitespace().count(), 20);
+        // long words are left for the view to wrap
+        let text = render("<p>日本 日本語日本語日本語日本語日本語日本語日本語</p>", 20);
+        assert_eq!(text.lines().count(), 2);
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(decode_entities("a &amp; b &lt;&gt; &auml;"), "a & b <> ä");
+        assert_eq!(decode_entities("&#228;&#xE4;&#X1F600;"), "ää😀");
+        assert_eq!(decode_entities("&unknown; & &amp"), "&unknown; & &amp");
+        assert_eq!(decode_entities("zero&zwj;width"), "zerowidth");
+        assert_eq!(decode_entities(""), "");
+    }
+
+    #[test]
+    fn control_characters_are_not_passed() {
+        assert_eq!(
+            decode_entities("&#27;[2J&#0;&#x9b;&#127;"),
+            "\u{fffd}[2J\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(decode_entities("&#10;&#9;"), "\n\t");
+        assert_eq!(decode_entities("raw\x1b[31m\r\x07"), "raw[31m");
+        assert!(
+            !render("<p>&#27;]0;title&#7;</p>", 40).contains(|c: char| c.is_control() && c != '\n')
+        );
+    }
+
+    #[test]
+    fn anchor_texts() {
+        assert_eq!(
+            anchors("<a href=' https://x.example '>see <b>this</b>\n<img alt='logo'></a><a href='#a'>x</a>"),
+            vec![("https://x.example".to_string(), "see this logo".to_string())]
+        );
+        assert!(anchors("<a>no href</a>").is_empty());
+    }
+
+    #[test]
+    fn broken_markup_is_safe() {
+        for html in &[
+            "<",
+            "<a",
+            "</",
+            "<!--",
+            "<p",
+            "a < b",
+            "<script>",
+            "<table><td>",
+            "</ul></blockquote>",
+            "&#xffffffff;",
+        ] {
+            render(html, 0);
+            anchors(html);
+        }
+        assert_eq!(render("a < b", 40), "a < b\n");
+    }
+}
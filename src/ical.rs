@@ -0,0 +1,531 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use log::*;
+
+#[derive(Debug, Clone, Default)]
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+    // unfolded source line
+    pub raw: String,
+}
+
+impl Property {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+}
+
+impl Component {
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> + 'a {
+        self.properties.iter().filter(move |p| p.name == name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<String> {
+        self.get(name).map(|p| unescape(&p.value))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Attendee {
+    pub name: Option<String>,
+    pub address: String,
+    pub status: String,
+}
+
+impl Attendee {
+    fn from(prop: &Property) -> Self {
+        Attendee {
+            name: prop.param("CN").map(|s| s.to_string()),
+            address: mailto(&prop.value),
+            status: prop.param("PARTSTAT").unwrap_or("NEEDS-ACTION").to_string(),
+        }
+    }
+
+    pub fn display(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} <{}>", name, self.address),
+            None => self.address.to_string(),
+        }
+    }
+}
+
+pub struct Event {
+    pub method: Option<String>,
+    pub summary: String,
+    pub organizer: Option<Attendee>,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub location: Option<String>,
+    pub attendees: Vec<Attendee>,
+    event: Component,
+}
+
+fn mailto(value: &str) -> String {
+    let value = value.trim();
+    match value.get(..7) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("mailto:") => value[7..].to_string(),
+        _ => value.to_string(),
+    }
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+
+    while let Some(ch) = chars.next() {
+        match (ch, ch == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') | Some('N') => result.push('\n'),
+                Some(c) => result.push(c),
+                None => {}
+            },
+            _ => result.push(ch),
+        }
+    }
+
+    result
+}
+
+fn parse_property(line: &str) -> Option<Property> {
+    // split at first ':' outside of quoted parameter values
+    let mut quoted = false;
+    let pos = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+
+    let mut parts = line[..pos].split(';');
+    let name = parts.next()?.trim().to_uppercase();
+    let params = parts
+        .filter_map(|param| {
+            let eq = param.find('=')?;
+            Some((
+                param[..eq].trim().to_uppercase(),
+                param[eq + 1..].trim_matches('"').to_string(),
+            ))
+        })
+        .collect();
+
+    Some(Property {
+        name,
+        params,
+        value: line[pos + 1..].to_string(),
+        raw: line.to_string(),
+    })
+}
+
+pub fn parse(data: &str) -> Option<Component> {
+    // unfold continuation lines
+    let mut lines: Vec<String> = vec![];
+    for line in data.lines() {
+        let line = line.trim_end_matches('\r');
+        match (
+            line.starts_with(' ') || line.starts_with('\t'),
+            lines.last_mut(),
+        ) {
+            (true, Some(last)) => last.push_str(&line[1..]),
+            _ if !line.is_empty() => lines.push(line.to_string()),
+            _ => {}
+        }
+    }
+
+    let mut stack: Vec<Component> = vec![];
+    for line in lines {
+        let prop = match parse_property(&line) {
+            Some(prop) => prop,
+            None => continue,
+        };
+        match prop.name.as_str() {
+            "BEGIN" => stack.push(Component {
+                name: prop.value.trim().to_uppercase(),
+                ..Component::default()
+            }),
+            "END" => {
+                let component = stack.pop()?;
+                match stack.last_mut() {
+                    Some(parent) => parent.components.push(component),
+                    None => return Some(component),
+                }
+            }
+            _ => stack.last_mut()?.properties.push(prop),
+        }
+    }
+
+    None
+}
+
+// nth weekday of month, negative n counts from the end
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: i32) -> Option<NaiveDate> {
+    if n > 0 {
+        let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let offset = (7 + weekday.num_days_from_monday() as i64
+            - first.weekday().num_days_from_monday() as i64)
+            % 7;
+        Some(first + Duration::days(offset + 7 * (n as i64 - 1)))
+    } else {
+        let next = match month {
+            12 => NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+            _ => NaiveDate::from_ymd_opt(year, month + 1, 1)?,
+        };
+        let last = next - Duration::days(1);
+        let offset = (7 + last.weekday().num_days_from_monday() as i64
+            - weekday.num_days_from_monday() as i64)
+            % 7;
+        Some(last - Duration::days(offset + 7 * (-n as i64 - 1)))
+    }
+}
+
+fn parse_offset(value: &str) -> Option<i32> {
+    let value = value.trim();
+    let sign = if value.starts_with('-') { -1 } else { 1 };
+    let digits = value.trim_start_matches(|c| c == '+' || c == '-');
+    let hours: i32 = digits.get(0..2)?.parse().ok()?;
+    let minutes: i32 = digits.get(2..4)?.parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn parse_local(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S").ok()
+}
+
+// transition of STANDARD/DAYLIGHT observance in given year,
+// supports the common yearly BYMONTH/BYDAY rules
+fn transition(observance: &Component, year: i32) -> Option<NaiveDateTime> {
+    let start = parse_local(&observance.get("DTSTART")?.value)?;
+    let rule = match observance.get("RRULE") {
+        Some(rule) => rule.value.to_uppercase(),
+        None => return Some(start),
+    };
+
+    // invitations come from anyone, malformed rules are ignored
+    let part = |name: &str| {
+        rule.split(';')
+            .find_map(|p| p.strip_prefix(name)?.strip_prefix('='))
+            .map(|p| p.to_string())
+    };
+    let month: u32 = part("BYMONTH")?.parse().ok()?;
+    let byday = part("BYDAY")?;
+    if byday.len() < 2 || !byday.is_char_boundary(byday.len() - 2) {
+        return None;
+    }
+    let (n, day) = byday.split_at(byday.len() - 2);
+    let weekday = match day {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    };
+    let n: i32 = match n {
+        "" => 1,
+        _ => n.parse().ok()?,
+    };
+    // a month has at most five of each weekday
+    if n == 0 || !(-5..=5).contains(&n) {
+        return None;
+    }
+
+    Some(nth_weekday(year, month, weekday, n)?.and_time(start.time()))
+}
+
+// utc offset in seconds for local time in VTIMEZONE
+fn tz_offset(tz: &Component, local: NaiveDateTime) -> Option<i32> {
+    let mut best: Option<(NaiveDateTime, i32)> = None;
+
+    for observance in &tz.components {
+        let offset = match observance
+            .get("TZOFFSETTO")
+            .and_then(|p| parse_offset(&p.value))
+        {
+            Some(offset) => offset,
+            None => continue,
+        };
+        // latest transition before the time, looking into previous year too
+        for year in &[local.year() - 1, local.year()] {
+            if let Some(at) = transition(observance, *year) {
+                if at <= local && best.map_or(true, |(b, _)| at > b) {
+                    best = Some((at, offset));
+                }
+            }
+        }
+    }
+
+    best.map(|(_, offset)| offset)
+}
+
+// date-time property in local time zone
+fn local_time(calendar: &Component, prop: &Property) -> Option<String> {
+    let value = prop.value.trim();
+
+    if prop.param("VALUE") == Some("DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Some(date.format("%a %Y-%m-%d").to_string());
+    }
+
+    let naive = parse_local(value)?;
+    let utc = if value.ends_with('Z') {
+        Some(naive)
+    } else {
+        prop.param("TZID")
+            .and_then(|tzid| {
+                calendar
+                    .components
+                    .iter()
+                    .filter(|c| c.name == "VTIMEZONE")
+                    .find(|c| c.get("TZID").map(|p| p.value.as_str()) == Some(tzid))
+            })
+            .and_then(|tz| tz_offset(tz, naive))
+            .map(|offset| naive - Duration::seconds(offset as i64))
+    };
+
+    let local = match utc {
+        Some(utc) => Utc.from_utc_datetime(&utc).with_timezone(&Local),
+        // floating time
+        None => Local.from_local_datetime(&naive).earliest()?,
+    };
+
+    Some(local.format("%a %Y-%m-%d %H:%M").to_string())
+}
+
+impl Event {
+    pub fn from(calendar: &Component) -> Option<Event> {
+        let event = calendar.components.iter().find(|c| c.name == "VEVENT")?;
+
+        Some(Event {
+            method: calendar.value("METHOD").map(|m| m.to_uppercase()),
+            summary: event.value("SUMMARY").unwrap_or_default(),
+            organizer: event.get("ORGANIZER").map(Attendee::from),
+            start: event.get("DTSTART").and_then(|p| local_time(calendar, p)),
+            end: event.get("DTEND").and_then(|p| local_time(calendar, p)),
+            location: event.value("LOCATION"),
+            attendees: event.all("ATTENDEE").map(Attendee::from).collect(),
+            event: event.clone(),
+        })
+    }
+
+    pub fn is_request(&self) -> bool {
+        self.method.as_deref() == Some("REQUEST")
+    }
+
+    // iTIP REPLY with 'partstat' for attendee 'address'
+    pub fn reply(&self, address: &str, name: &str, partstat: &str) -> String {
+        debug!("Event::reply: {} {}", address, partstat);
+
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "PRODID:-//nutt//EN".to_string(),
+            "VERSION:2.0".to_string(),
+            "METHOD:REPLY".to_string(),
+            "BEGIN:VEVENT".to_string(),
+        ];
+        for name in &["UID", "SEQUENCE", "RECURRENCE-ID", "ORGANIZER", "SUMMARY"] {
+            if let Some(prop) = self.event.get(name) {
+                lines.push(prop.raw.to_string());
+            }
+        }
+        lines.push(format!("DTSTAMP:{}", Utc::now().format("%Y%m%dT%H%M%SZ")));
+        lines.push(format!(
+            "ATTENDEE;PARTSTAT={};CN=\"{}\":mailto:{}",
+            partstat,
+            name.replace('"', ""),
+            address
+        ));
+        lines.push("END:VEVENT".to_string());
+        lines.push("END:VCALENDAR".to_string());
+
+        // fold lines longer than 75 octets, never inside a character
+        let mut result = String::new();
+        for line in lines {
+            let mut rest = line.as_str();
+            while rest.len() > 74 {
+                let mut pos = 74;
+                while !rest.is_char_boundary(pos) {
+                    pos -= 1;
+                }
+                result.push_str(&rest[..pos]);
+                result.push_str("\r\n ");
+                rest = &rest[pos..];
+            }
+            result.push_str(rest);
+            result.push_str("\r\n");
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVITE: &str = "BEGIN:VCALENDAR\r\n\
+METHOD:request\r\n\
+BEGIN:VTIMEZONE\r\n\
+TZID:Europe/Helsinki\r\n\
+BEGIN:STANDARD\r\n\
+DTSTART:19701025T040000\r\n\
+TZOFFSETTO:+0200\r\n\
+RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU\r\n\
+END:STANDARD\r\n\
+BEGIN:DAYLIGHT\r\n\
+DTSTART:19700329T030000\r\n\
+TZOFFSETTO:+0300\r\n\
+RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU\r\n\
+END:DAYLIGHT\r\n\
+END:VTIMEZONE\r\n\
+BEGIN:VEVENT\r\n\
+UID:1234@example.com\r\n\
+SUMMARY:Kokous\\, tärkeä\\nasia\r\n\
+ORGANIZER;CN=\"Boss: Big\":mailto:boss@example.com\r\n\
+ATTENDEE;CN=Äiti;PARTSTAT=ACCEPTED:MAILTO:mom@example.com\r\n\
+ATTENDEE:mailto:me@exa\r\n mple.com\r\n\
+DTSTART;VALUE=DATE:20200601\r\n\
+END:VEVENT\r\n\
+END:VCALENDAR\r\n";
+
+    fn observance(rrule: &str) -> Component {
+        Component {
+            name: "STANDARD".to_string(),
+            properties: vec![
+                parse_property("DTSTART:19701025T040000").unwrap(),
+                parse_property(&format!("RRULE:{}", rrule)).unwrap(),
+            ],
+            components: vec![],
+        }
+    }
+
+    #[test]
+    fn invitation_is_parsed() {
+        let calendar = parse(INVITE).unwrap();
+        let event = Event::from(&calendar).unwrap();
+
+        assert!(event.is_request());
+        assert_eq!(event.summary, "Kokous, tärkeä\nasia");
+        let organizer = event.organizer.unwrap();
+        assert_eq!(organizer.display(), "Boss: Big <boss@example.com>");
+        assert_eq!(event.attendees.len(), 2);
+        assert_eq!(event.attendees[0].display(), "Äiti <mom@example.com>");
+        assert_eq!(event.attendees[0].status, "ACCEPTED");
+        assert_eq!(event.attendees[1].address, "me@example.com");
+        assert_eq!(event.attendees[1].status, "NEEDS-ACTION");
+        assert_eq!(event.start.as_deref(), Some("Mon 2020-06-01"));
+        assert!(event.end.is_none());
+    }
+
+    #[test]
+    fn broken_calendars_are_rejected() {
+        assert!(parse("").is_none());
+        assert!(parse("BEGIN:VCALENDAR\r\nSUMMARY:x\r\n").is_none());
+        assert!(parse("END:VCALENDAR\r\n").is_none());
+        assert!(parse("no colon\r\n BEGIN\r\n").is_none());
+    }
+
+    #[test]
+    fn transitions_follow_yearly_rules() {
+        let last_sunday = observance("FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU");
+        assert_eq!(
+            transition(&last_sunday, 2020),
+            Some(
+                NaiveDate::from_ymd_opt(2020, 10, 25)
+                    .unwrap()
+                    .and_hms_opt(4, 0, 0)
+                    .unwrap()
+            )
+        );
+        let second_sunday = observance("FREQ=YEARLY;BYMONTH=3;BYDAY=2SU");
+        assert_eq!(
+            transition(&second_sunday, 2020),
+            Some(
+                NaiveDate::from_ymd_opt(2020, 3, 8)
+                    .unwrap()
+                    .and_hms_opt(4, 0, 0)
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn malformed_rules_are_ignored() {
+        for rule in &[
+            "BYMONTH;BYDAY=-1SU",
+            "BYMONTH=10;BYDAY",
+            "BYMONTH=10;BYDAY=",
+            "BYMONTH=10;BYDAY=S",
+            "BYMONTH=10;BYDAY=Ä",
+            "BYMONTH=10;BYDAY=1ÄU",
+            "BYMONTH=10;BYDAY=1XX",
+            "BYMONTH=10;BYDAY=0SU",
+            "BYMONTH=10;BYDAY=2147483647SU",
+            "BYMONTH=10;BYDAY=-2147483648SU",
+            "BYMONTH=13;BYDAY=1SU",
+            "BYMONTHDAY=1;BYDAY=1SU",
+        ] {
+            assert_eq!(transition(&observance(rule), 2020), None, "{}", rule);
+        }
+    }
+
+    #[test]
+    fn offsets_come_from_latest_transition() {
+        let calendar = parse(INVITE).unwrap();
+        let tz = &calendar.components[0];
+        let winter = NaiveDate::from_ymd_opt(2020, 1, 15)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let summer = NaiveDate::from_ymd_opt(2020, 7, 15)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        assert_eq!(tz_offset(tz, winter), Some(2 * 3600));
+        assert_eq!(tz_offset(tz, summer), Some(3 * 3600));
+        assert_eq!(parse_offset("-0130"), Some(-5400));
+        assert_eq!(parse_offset("+2"), None);
+    }
+
+    #[test]
+    fn nth_weekday_counts_from_both_ends() {
+        let first = nth_weekday(2020, 2, Weekday::Sat, 1).unwrap();
+        let last = nth_weekday(2020, 12, Weekday::Thu, -1).unwrap();
+        assert_eq!(first, NaiveDate::from_ymd_opt(2020, 2, 1).unwrap());
+        assert_eq!(last, NaiveDate::from_ymd_opt(2020, 12, 31).unwrap());
+    }
+
+    #[test]
+    fn reply_is_folded_by_octets() {
+        let calendar = parse(INVITE).unwrap();
+        let event = Event::from(&calendar).unwrap();
+        let reply = event.reply("me@example.com", &"Ääkkönen ".repeat(10), "DECLINED");
+
+        assert!(reply.contains("METHOD:REPLY\r\n"));
+        assert!(reply.contains("UID:1234@example.com\r\n"));
+        for line in reply.split("\r\n") {
+            assert!(line.len() <= 75, "{}", line);
+        }
+        let unfolded = reply.replace("\r\n ", "");
+        assert!(unfolded.contains("PARTSTAT=DECLINED;CN=\"Ääkkönen "));
+        assert!(unfolded.contains(":mailto:me@example.com\r\n"));
+    }
+}
@@ -0,0 +1,155 @@
+// terminal events from one parser for the whole session, termion
+// keeps bytes read ahead inside its iterator so a new iterator per
+// event would lose input
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use termion::event::{Event, Key};
+use termion::input::TermRead;
+
+// events are read on a thread only when asked for, so that waiting
+// can time out and nothing is read while the editor owns the terminal
+pub struct Input {
+    requests: Sender<()>,
+    events: Receiver<io::Result<Event>>,
+    // event asked for and not yet received
+    pending: Cell<bool>,
+    // event received while waiting
+    ready: RefCell<Option<io::Result<Event>>>,
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "input closed")
+}
+
+impl Input {
+    pub fn new<R: Read + Send + 'static>(source: R) -> Input {
+        let (requests, requested) = mpsc::channel::<()>();
+        let (sender, events) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut parser = source.events();
+            for _ in requested {
+                let event = parser.next().unwrap_or_else(|| Err(closed()));
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Input {
+            requests,
+            events,
+            pending: Cell::new(false),
+            ready: RefCell::new(None),
+        }
+    }
+
+    fn request(&self) {
+        if !self.pending.replace(true) {
+            let _ = self.requests.send(());
+        }
+    }
+
+    // false when timeout passed without input, no timeout waits for
+    // the next event instead
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let timeout = match timeout {
+            Some(timeout) if self.ready.borrow().is_none() => timeout,
+            _ => return true,
+        };
+        self.request();
+        let event = match self.events.recv_timeout(timeout) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return false,
+            Err(RecvTimeoutError::Disconnected) => Err(closed()),
+        };
+        self.pending.set(false);
+        *self.ready.borrow_mut() = Some(event);
+        true
+    }
+
+    pub fn event(&self) -> io::Result<Event> {
+        if let Some(event) = self.ready.borrow_mut().take() {
+            return event;
+        }
+        self.request();
+        let event = self.events.recv().unwrap_or_else(|_| Err(closed()));
+        self.pending.set(false);
+        event
+    }
+
+    // next key, other events are skipped
+    pub fn key(&self) -> io::Result<Key> {
+        loop {
+            if let Event::Key(key) = self.event()? {
+                return Ok(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::{KillRing, Prompt, PromptEvent};
+    use std::io::Cursor;
+
+    fn input(bytes: &[u8]) -> Input {
+        Input::new(Cursor::new(bytes.to_vec()))
+    }
+
+    #[test]
+    fn bytes_read_ahead_are_kept() {
+        let input = input("aä\x1b[Ab".as_bytes());
+        assert_eq!(input.key().unwrap(), Key::Char('a'));
+        assert_eq!(input.key().unwrap(), Key::Char('ä'));
+        assert_eq!(input.key().unwrap(), Key::Up);
+        assert_eq!(input.key().unwrap(), Key::Char('b'));
+        assert!(input.key().is_err());
+        assert!(input.event().is_err());
+    }
+
+    #[test]
+    fn pasted_text_reaches_prompt() {
+        let input = input(b"\x1b[200~hello world\x1b[201~\r");
+        let mut p = Prompt::new("", "");
+        let mut kill_ring = KillRing::default();
+        loop {
+            match p.handle_event(input.event().unwrap(), &mut kill_ring) {
+                PromptEvent::Submit(text) => {
+                    assert_eq!(text, "hello world");
+                    break;
+                }
+                PromptEvent::Cancel => panic!("paste cancelled"),
+                PromptEvent::Edit => {}
+            }
+        }
+        assert!(!p.is_pasting());
+    }
+
+    #[test]
+    fn waiting_keeps_the_event() {
+        let input = input(b"xy");
+        assert!(input.wait(Some(Duration::from_secs(5))));
+        assert!(input.wait(Some(Duration::from_secs(5))));
+        assert_eq!(input.key().unwrap(), Key::Char('x'));
+        assert!(input.wait(None));
+        assert_eq!(input.key().unwrap(), Key::Char('y'));
+    }
+
+    #[test]
+    fn waiting_times_out() {
+        // never ready, like a terminal nobody types on
+        struct Idle;
+        impl Read for Idle {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                std::thread::sleep(Duration::from_secs(60));
+                Ok(0)
+            }
+        }
+        let input = Input::new(Idle);
+        assert!(!input.wait(Some(Duration::from_millis(10))));
+        assert!(!input.wait(Some(Duration::from_millis(10))));
+    }
+}
@@ -0,0 +1,266 @@
+use crate::html;
+use failure::bail;
+use log::*;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+pub struct Link {
+    pub url: String,
+    // anchor text, empty for links found in plain text
+    pub text: String,
+    // domain shown in anchor text if it differs from the url
+    pub mismatch: Option<String>,
+}
+
+const SCHEMES: [&str; 4] = ["https://", "http://", "ftp://", "mailto:"];
+
+// urls found in plain text
+fn text_urls(text: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut rest = text;
+
+    while let Some((pos, _)) = SCHEMES
+        .iter()
+        .filter_map(|scheme| rest.find(scheme).map(|pos| (pos, scheme)))
+        .min()
+    {
+        rest = &rest[pos..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || "<>\"'`".contains(c))
+            .unwrap_or(rest.len());
+        let mut url = &rest[..end];
+        rest = &rest[end..];
+
+        // trailing punctuation belongs to the sentence
+        loop {
+            let trimmed = url.trim_end_matches(|c| ".,;:!?".contains(c));
+            let trimmed = match trimmed.ends_with(')')
+                && trimmed.matches(')').count() > trimmed.matches('(').count()
+            {
+                true => &trimmed[..trimmed.len() - 1],
+                _ => trimmed,
+            };
+            if trimmed == url {
+                break;
+            }
+            url = trimmed;
+        }
+
+        if !SCHEMES.contains(&url) {
+            result.push(url.to_string());
+        }
+    }
+
+    result
+}
+
+pub fn host(url: &str) -> Option<String> {
+    let rest = match url.find("://") {
+        Some(pos) => &url[pos + 3..],
+        None if url.starts_with("mailto:") => return None,
+        None => url,
+    };
+    let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next()?;
+    let host = authority.rsplit('@').next()?.split(':').next()?;
+    let host = host.to_lowercase();
+    let host = host.trim_start_matches("www.");
+
+    match host.is_empty() {
+        true => None,
+        _ => Some(host.to_string()),
+    }
+}
+
+// endings of file names that anchor texts often show
+const FILE_EXTENSIONS: [&str; 24] = [
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "ods", "txt", "csv", "rtf", "zip",
+    "gz", "tar", "rar", "7z", "jpg", "jpeg", "png", "gif", "mov", "mp3", "mp4",
+];
+
+// domain if the anchor text looks like an url or a host name
+fn text_domain(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() || text.contains(char::is_whitespace) {
+        return None;
+    }
+    let host = host(text)?;
+    let tld = host.rsplit('.').next()?;
+    // bare names like report.pdf are files, not hosts
+    let explicit = text.contains("://") || text.to_lowercase().starts_with("www.");
+    let file = !explicit && FILE_EXTENSIONS.contains(&tld);
+    match host.contains('.')
+        && tld.len() >= 2
+        && tld.chars().all(|c| c.is_ascii_alphabetic())
+        && !file
+    {
+        true => Some(host),
+        _ => None,
+    }
+}
+
+fn mismatch(url: &str, text: &str) -> Option<String> {
+    let shown = text_domain(text)?;
+    match host(url) {
+        Some(real) if real == shown || real.ends_with(&format!(".{}", shown)) => None,
+        _ => Some(shown),
+    }
+}
+
+pub fn extract(body: &str, html: Option<&str>) -> Vec<Link> {
+    debug!("links::extract");
+
+    let mut result: Vec<Link> = vec![];
+    let anchors = html.map(html::anchors).unwrap_or_default();
+    let urls = text_urls(body).into_iter().map(|url| (url, String::new()));
+
+    for (url, text) in urls.chain(anchors) {
+        match result.iter_mut().find(|link| link.url == url) {
+            Some(link) if link.text.is_empty() && !text.is_empty() => {
+                link.mismatch = mismatch(&url, &text);
+                link.text = text;
+            }
+            Some(_) => {}
+            None => result.push(Link {
+                mismatch: mismatch(&url, &text),
+                url,
+                text,
+            }),
+        }
+    }
+
+    result
+}
+
+pub fn open(url: &str, opener: &str) -> Result<(), failure::Error> {
+    debug!("links::open: {}", url);
+
+    let mut command = opener.split_whitespace();
+    let status = Command::new(command.next().unwrap_or("xdg-open"))
+        .args(command)
+        .arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        bail!("'{}' failed: {}", opener, status);
+    }
+
+    Ok(())
+}
+
+pub fn copy(url: &str, clipboard: &str) -> Result<(), failure::Error> {
+    debug!("links::copy: {}", url);
+
+    let mut command = clipboard.split_whitespace();
+    let mut child = Command::new(command.next().unwrap_or("xclip"))
+        .args(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let stdin = child
+        .stdin
+        .as_mut()
+        .ok_or(failure::format_err!("Failed to run '{}'", clipboard))?;
+    stdin.write_all(url.as_bytes())?;
+    drop(child.stdin.take());
+    child.wait()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_in_text() {
+        assert_eq!(
+            text_urls("See https://example.com/a, (http://x.example/b) or <ftp://f.example>."),
+            vec![
+                "https://example.com/a",
+                "http://x.example/b",
+                "ftp://f.example"
+            ]
+        );
+        assert_eq!(
+            text_urls("wiki: https://en.wikipedia.org/wiki/Rust_(kieli)!"),
+            vec!["https://en.wikipedia.org/wiki/Rust_(kieli)"]
+        );
+        assert_eq!(
+            text_urls("mailto:ä@example.com?"),
+            vec!["mailto:ä@example.com"]
+        );
+        assert!(text_urls("").is_empty());
+        assert!(text_urls("just https:// here").is_empty());
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(
+            host("https://user@WWW.Example.com:8080/x"),
+            Some("example.com".into())
+        );
+        assert_eq!(host("example.com/path"), Some("example.com".into()));
+        assert_eq!(host("mailto:a@example.com"), None);
+        assert_eq!(host("https://"), None);
+    }
+
+    #[test]
+    fn shown_domain_must_match() {
+        assert_eq!(
+            mismatch("https://paypal.com.evil.net/login", "paypal.com"),
+            Some("paypal.com".into())
+        );
+        assert_eq!(
+            mismatch("https://evil.net", "https://www.paypal.com/"),
+            Some("paypal.com".into())
+        );
+        assert_eq!(
+            mismatch("mailto:x@evil.net", "paypal.com"),
+            Some("paypal.com".into())
+        );
+        assert_eq!(mismatch("https://www.paypal.com/x", "paypal.com"), None);
+        assert_eq!(mismatch("https://login.paypal.com", "paypal.com"), None);
+        // ending alone isn't a subdomain
+        assert_eq!(
+            mismatch("https://notpaypal.com", "paypal.com"),
+            Some("paypal.com".into())
+        );
+    }
+
+    #[test]
+    fn plain_anchor_texts_are_not_domains() {
+        assert_eq!(mismatch("https://files.example/1", "report.pdf"), None);
+        assert_eq!(
+            mismatch("https://files.example/1", "Quarterly report"),
+            None
+        );
+        assert_eq!(mismatch("https://files.example/1", "v1.2"), None);
+        assert_eq!(mismatch("https://files.example/1", ""), None);
+        assert_eq!(
+            mismatch("https://files.example/1", "https://report.pdf"),
+            Some("report.pdf".into())
+        );
+    }
+
+    #[test]
+    fn anchors_fill_in_text_links() {
+        let links = extract(
+            "https://a.example",
+            Some("<a href='https://a.example'>a.example</a><a href='https://evil.net'>bank.example</a>"),
+        );
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].text, "a.example");
+        assert_eq!(links[0].mismatch, None);
+        assert_eq!(links[1].mismatch, Some("bank.example".into()));
+    }
+
+    #[test]
+    fn failed_open_is_an_error() {
+        assert!(open("https://example.com", "true").is_ok());
+        assert!(open("https://example.com", "false").is_err());
+        assert!(open("https://example.com", "/nonexistent/opener").is_err());
+    }
+}
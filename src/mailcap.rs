@@ -0,0 +1,305 @@
+use crate::config;
+use log::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub content_type: String,
+    pub view: String,
+    pub copiousoutput: bool,
+    pub needsterminal: bool,
+    pub test: Option<String>,
+    pub nametemplate: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Mailcap {
+    entries: Vec<Entry>,
+}
+
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// fields separated by unescaped ';'
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut chars = line.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(';') => field.push(';'),
+                Some(next) => {
+                    field.push('\\');
+                    field.push(next);
+                }
+                None => {}
+            },
+            ';' => {
+                fields.push(field.trim().to_string());
+                field.clear();
+            }
+            _ => field.push(ch),
+        }
+    }
+    fields.push(field.trim().to_string());
+
+    fields
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let fields = split_fields(line);
+    if fields.len() < 2 {
+        return None;
+    }
+
+    let mut entry = Entry {
+        content_type: fields[0].to_lowercase(),
+        view: fields[1].to_string(),
+        copiousoutput: false,
+        needsterminal: false,
+        test: None,
+        nametemplate: None,
+    };
+    for field in &fields[2..] {
+        let (name, value) = match field.find('=') {
+            Some(pos) => (field[..pos].trim(), Some(field[pos + 1..].trim())),
+            None => (field.as_str(), None),
+        };
+        match (name.to_lowercase().as_str(), value) {
+            ("copiousoutput", _) => entry.copiousoutput = true,
+            ("needsterminal", _) => entry.needsterminal = true,
+            ("test", Some(value)) => entry.test = Some(value.to_string()),
+            ("nametemplate", Some(value)) => entry.nametemplate = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    Some(entry)
+}
+
+pub fn parse(data: &str) -> Vec<Entry> {
+    let mut entries = vec![];
+    let mut line = String::new();
+
+    for l in data.lines() {
+        if line.is_empty() && (l.trim_start().starts_with('#') || l.trim().is_empty()) {
+            continue;
+        }
+        // continuation lines
+        if l.ends_with('\\') && !l.ends_with("\\\\") {
+            line.push_str(&l[..l.len() - 1]);
+            continue;
+        }
+        line.push_str(l);
+        if let Some(entry) = parse_entry(&line) {
+            entries.push(entry);
+        }
+        line.clear();
+    }
+
+    entries
+}
+
+// nutt specific file first, then $MAILCAPS or the standard locations
+fn paths() -> Vec<PathBuf> {
+    let mut result = vec![];
+
+    if let Some(dir) = config::config_dir() {
+        result.push(dir.join("mailcap"));
+    }
+    match std::env::var("MAILCAPS") {
+        Ok(mailcaps) => result.extend(mailcaps.split(':').map(PathBuf::from)),
+        Err(_) => {
+            if let Some(home) = dirs::home_dir() {
+                result.push(home.join(".mailcap"));
+            }
+            result.push(PathBuf::from("/etc/mailcap"));
+        }
+    }
+
+    result
+}
+
+fn type_matches(pattern: &str, content_type: &str) -> bool {
+    let content_type = content_type.to_lowercase();
+    match pattern.find('/') {
+        Some(pos) if &pattern[pos + 1..] == "*" => {
+            content_type.split('/').next() == Some(&pattern[..pos])
+        }
+        Some(_) => pattern == content_type,
+        None => content_type.split('/').next() == Some(pattern),
+    }
+}
+
+impl Mailcap {
+    pub fn load() -> Mailcap {
+        let mut entries = vec![];
+
+        for path in paths() {
+            match std::fs::read_to_string(&path) {
+                Ok(data) => {
+                    debug!("Mailcap::load: {:?}", &path);
+                    entries.extend(parse(&data));
+                }
+                Err(e) => debug!("Mailcap::load: {:?}: {}", &path, e),
+            }
+        }
+
+        Mailcap { entries }
+    }
+
+    pub fn lookup(&self, content_type: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .filter(|e| type_matches(&e.content_type, content_type))
+            .find(|e| e.test_passes(content_type))
+    }
+}
+
+impl Entry {
+    // %s file, %t content type, %{param} parameters other than charset are not available
+    fn expand(&self, template: &str, file: &str, content_type: &str) -> String {
+        let mut result = String::new();
+        let mut chars = template.chars();
+
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                result.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('s') => result.push_str(file),
+                Some('t') => result.push_str(&quote(content_type)),
+                Some('{') => {
+                    let param: String = (&mut chars).take_while(|c| *c != '}').collect();
+                    match param.to_lowercase().as_str() {
+                        // html alternatives are stored as utf-8
+                        "charset" => result.push_str("utf-8"),
+                        _ => result.push_str("''"),
+                    }
+                }
+                Some(c) => result.push(c),
+                None => result.push('%'),
+            }
+        }
+
+        result
+    }
+
+    fn test_passes(&self, content_type: &str) -> bool {
+        match &self.test {
+            Some(test) => Command::new("sh")
+                .arg("-c")
+                .arg(self.expand(test, "''", content_type))
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .map(|status| status.success())
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    // file name following nametemplate, e.g. '%s.html'
+    pub fn filename(&self, name: &str) -> String {
+        match &self.nametemplate {
+            Some(template) => {
+                let suffix = template.replace("%s", "");
+                match name.ends_with(&suffix) {
+                    true => name.to_string(),
+                    _ => template.replace("%s", name),
+                }
+            }
+            None => name.to_string(),
+        }
+    }
+
+    // view command, file is given in stdin if the command has no '%s'
+    pub fn command(&self, path: &Path, content_type: &str) -> Result<Command, failure::Error> {
+        let file = quote(&path.display().to_string());
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(self.expand(&self.view, &file, content_type));
+        if !self.view.contains("%s") {
+            command.stdin(File::open(path)?);
+        }
+
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_parsed_with_flags() {
+        let entries = parse(
+            "# comment\n\
+             \n\
+             text/html; w3m -dump %s; nametemplate=%s.html; copiousoutput\n\
+             image/*; feh \\\n  %s; needsterminal; test=test -n \"$DISPLAY\"\n\
+             broken\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].content_type, "text/html");
+        assert_eq!(entries[0].view, "w3m -dump %s");
+        assert!(entries[0].copiousoutput && !entries[0].needsterminal);
+        assert_eq!(entries[0].nametemplate.as_deref(), Some("%s.html"));
+        assert_eq!(entries[1].view, "feh   %s");
+        assert!(entries[1].needsterminal);
+        assert_eq!(entries[1].test.as_deref(), Some("test -n \"$DISPLAY\""));
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn escaped_semicolons_stay_in_field() {
+        assert_eq!(
+            split_fields("text/plain; cat %s \\; echo; copiousoutput"),
+            vec!["text/plain", "cat %s ; echo", "copiousoutput"]
+        );
+    }
+
+    #[test]
+    fn types_match_wildcards_and_case() {
+        assert!(type_matches("image/*", "IMAGE/PNG"));
+        assert!(type_matches("image", "image/png"));
+        assert!(type_matches("text/html", "text/html"));
+        assert!(!type_matches("text/html", "text/plain"));
+        assert!(!type_matches("image/*", ""));
+    }
+
+    #[test]
+    fn first_matching_entry_is_found() {
+        let mailcap = Mailcap {
+            entries: parse("text/html; a %s\nimage/*; b %s\ntext/*; c %s\n"),
+        };
+        assert_eq!(mailcap.lookup("text/html").unwrap().view, "a %s");
+        assert_eq!(mailcap.lookup("text/plain").unwrap().view, "c %s");
+        assert!(mailcap.lookup("application/pdf").is_none());
+    }
+
+    #[test]
+    fn templates_are_expanded_and_quoted() {
+        let entry = &parse("text/html; view %s %t %{charset} %{name} 100%%\n")[0];
+        assert_eq!(
+            entry.expand(&entry.view, "'/tmp/a b'", "text/html"),
+            "view '/tmp/a b' 'text/html' utf-8 '' 100%"
+        );
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn file_names_follow_nametemplate() {
+        let entry = &parse("text/html; view %s; nametemplate=%s.html\n")[0];
+        assert_eq!(entry.filename("page"), "page.html");
+        assert_eq!(entry.filename("page.html"), "page.html");
+        assert_eq!(entry.filename("äö"), "äö.html");
+    }
+}
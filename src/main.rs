@@ -1,30 +1,145 @@
-use emailmessage::Message;
+use chrono::prelude::*;
+use compose::Draft;
+use config::Config;
 use log::*;
+use prompt::{Prompt, PromptEvent};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::io;
 use std::io::prelude::*;
 use std::io::Stdout;
 use std::process::{Command, Stdio};
 use termion::cursor::Goto;
-use termion::event::Key;
-use termion::input::TermRead;
+use termion::event::{Event, Key, MouseButton, MouseEvent};
 use termion::raw::{IntoRawMode, RawTerminal};
 use tui::backend::TermionBackend;
-use tui::layout::{Alignment, Constraint, Direction, Layout};
+use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
-use tui::widgets::{Block, Borders, Paragraph, Row, Table, Text};
-use tui::Terminal;
-
+use tui::widgets::{Block, Borders, Paragraph, Text};
+use tui::{Frame, Terminal};
+
+mod attachment;
+mod autocrypt;
+mod compose;
+mod config;
+mod diff;
+mod flowed;
+mod format;
+mod html;
+mod ical;
+mod input;
+mod links;
+mod mailcap;
+mod mouse;
 mod notmuch;
+mod pgp;
+mod pipe;
+mod prompt;
+mod quote;
+mod scroll;
+mod search;
+mod status;
+mod tmpdir;
+mod width;
 
 struct MessageList {
     list: Vec<notmuch::Message>,
     selected: u16,
+    // ids of messages marked for bulk actions
+    marked: HashSet<String>,
+    // start of range being marked
+    anchor: Option<u16>,
 }
 
 impl MessageList {
     fn new(list: Vec<notmuch::Message>) -> Self {
-        MessageList { list, selected: 0 }
+        MessageList {
+            list,
+            selected: 0,
+            marked: HashSet::new(),
+            anchor: None,
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(msg) = self.list.get(self.selected as usize) {
+            if !self.marked.remove(&msg.id) {
+                self.marked.insert(msg.id.to_string());
+            }
+        }
+    }
+
+    fn mark_thread(&mut self) {
+        if let Some(thread) = self.list.get(self.selected as usize).map(|m| m.thread) {
+            for msg in self.list.iter().filter(|m| m.thread == thread) {
+                self.marked.insert(msg.id.to_string());
+            }
+        }
+    }
+
+    // first call sets the anchor, second marks rows between it and selected
+    fn mark_range(&mut self) {
+        match self.anchor.take() {
+            Some(anchor) => {
+                let (start, end) = match anchor < self.selected {
+                    true => (anchor, self.selected),
+                    _ => (self.selected, anchor),
+                };
+                for msg in &self.list[start as usize..=end as usize] {
+                    self.marked.insert(msg.id.to_string());
+                }
+            }
+            None => self.anchor = Some(self.selected),
+        }
+    }
+
+    fn mark_ids(&mut self, ids: &[String]) -> usize {
+        let mut count = 0;
+        for msg in self.list.iter().filter(|m| ids.contains(&m.id)) {
+            self.marked.insert(msg.id.to_string());
+            count += 1;
+        }
+        count
+    }
+
+    fn clear_marks(&mut self) {
+        self.marked.clear();
+        self.anchor = None;
+    }
+
+    fn is_marked(&self, msg: &notmuch::Message) -> bool {
+        self.marked.contains(&msg.id)
+    }
+
+    // marked messages in list order, selected one if none marked
+    fn targets(&self) -> Vec<String> {
+        match self.marked.is_empty() {
+            true => self
+                .get_selected()
+                .map(|m| vec![m.id.to_string()])
+                .unwrap_or_default(),
+            _ => self
+                .list
+                .iter()
+                .filter(|m| self.is_marked(m))
+                .map(|m| m.id.to_string())
+                .collect(),
+        }
+    }
+
+    // update tags of listed messages after 'notmuch tag'
+    fn apply_tags(&mut self, ids: &[String], changes: &[String]) {
+        for msg in self.list.iter_mut().filter(|m| ids.contains(&m.id)) {
+            for change in changes {
+                let tag = change[1..].to_string();
+                match change.starts_with('+') {
+                    true if !msg.tags.contains(&tag) => msg.tags.push(tag),
+                    false => msg.tags.retain(|t| *t != tag),
+                    _ => {}
+                }
+            }
+            msg.tags.sort();
+        }
     }
 
     fn select_next(&mut self) {
@@ -44,7 +159,13 @@ impl MessageList {
     }
 
     fn select_last(&mut self) {
-        self.selected = self.list.len() as u16 - 1;
+        self.selected = self.len().saturating_sub(1);
+    }
+
+    // move selection by lines, stops at the ends
+    fn select_by(&mut self, lines: i32) {
+        let selected = std::cmp::max(self.selected as i32 + lines, 0);
+        self.selected = std::cmp::min(selected as u16, self.len().saturating_sub(1));
     }
 
     fn get_selected(&self) -> Result<&notmuch::Message, failure::Error> {
@@ -57,26 +178,68 @@ impl MessageList {
         }
     }
 
+    fn select_id(&mut self, id: &str) {
+        if let Some(pos) = self.list.iter().position(|m| m.id == id) {
+            self.selected = pos as u16;
+        }
+    }
+
     fn len(&self) -> u16 {
         self.list.len() as u16
     }
+
+    fn unread(&self) -> usize {
+        self.list
+            .iter()
+            .filter(|m| m.tags.iter().any(|t| t == "unread"))
+            .count()
+    }
 }
 
 enum AppState {
     Refresh,
     Index,
     View,
+    Thread,
     _EditSubject,
     Compose,
     Exit,
 }
 
+impl AppState {
+    // shown on the status bar
+    fn name(&self) -> &str {
+        match self {
+            AppState::Refresh => "REFRESH",
+            AppState::Index => "INDEX",
+            AppState::View => "VIEW",
+            AppState::Thread => "THREAD",
+            AppState::_EditSubject => "EDIT",
+            AppState::Compose => "COMPOSE",
+            AppState::Exit => "EXIT",
+        }
+    }
+}
+
 struct Styles {
     selected: Style,
+    marked: Style,
     header: Style,
     normal: Style,
     subject: Style,
     attachment: Style,
+    error: Style,
+    highlight: Style,
+    // quote levels cycle through these
+    quotes: Vec<Style>,
+    signature: Style,
+    diff_file: Style,
+    diff_hunk: Style,
+    diff_add: Style,
+    diff_remove: Style,
+    status: Style,
+    // tag styles from config
+    tags: HashMap<String, Style>,
 }
 
 struct Tags<'a>(&'a Vec<String>);
@@ -95,64 +258,316 @@ impl<'a> fmt::Display for Tags<'a> {
     }
 }
 
+fn parse_color(name: &str) -> Option<Color> {
+    let color = match name {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "white" => Color::White,
+        _ if name.starts_with('#') && name.len() == 7 => Color::Rgb(
+            u8::from_str_radix(&name[1..3], 16).ok()?,
+            u8::from_str_radix(&name[3..5], 16).ok()?,
+            u8::from_str_radix(&name[5..7], 16).ok()?,
+        ),
+        _ => return None,
+    };
+
+    Some(color)
+}
+
+// style from spec like "yellow bold" or "white on #303030"
+fn parse_style(spec: &str) -> Style {
+    let mut style = Style::default();
+    let mut modifier = Modifier::empty();
+    let mut background = false;
+
+    for word in spec.split_whitespace().map(|w| w.to_lowercase()) {
+        match word.as_str() {
+            "on" => background = true,
+            "bold" => modifier |= Modifier::BOLD,
+            "dim" => modifier |= Modifier::DIM,
+            "italic" => modifier |= Modifier::ITALIC,
+            "underline" => modifier |= Modifier::UNDERLINED,
+            "reverse" => modifier |= Modifier::REVERSED,
+            _ => match (parse_color(&word), background) {
+                (Some(color), true) => style = style.bg(color),
+                (Some(color), false) => style = style.fg(color),
+                (None, _) => warn!("parse_style: unknown style: {}", word),
+            },
+        }
+    }
+
+    style.modifier(modifier)
+}
+
 struct App {
     state: AppState,
     messages: MessageList,
     styles: Styles,
     search_term: String,
+    sort: config::Sort,
+    config: Config,
+    autocrypt: autocrypt::Store,
+    tmp_dir: tmpdir::TmpDir,
+    mailcap: mailcap::Mailcap,
+    // thread order or flat newest first
+    threaded: bool,
+    pipe_history: pipe::History,
+    kill_ring: prompt::KillRing,
+    status: status::Status,
+    input: input::Input,
 }
 
 impl App {
-    fn new() -> App {
+    fn new(config: Config, autocrypt: autocrypt::Store, tmp_dir: tmpdir::TmpDir) -> App {
+        let search = config.searches[0].clone();
+        let tag_styles = config
+            .tags
+            .iter()
+            .filter_map(|(tag, rule)| {
+                rule.style
+                    .as_ref()
+                    .map(|spec| (tag.to_string(), parse_style(spec)))
+            })
+            .collect();
         App {
             state: AppState::Refresh,
-            search_term: "tag:inbox".to_string(),
+            config,
+            autocrypt,
+            tmp_dir,
+            mailcap: mailcap::Mailcap::load(),
+            search_term: search.query,
+            sort: search.sort,
+            threaded: true,
+            pipe_history: pipe::History::load(),
+            kill_ring: prompt::KillRing::default(),
+            status: status::Status::default(),
+            input: input::Input::new(io::stdin()),
             messages: MessageList::new(vec![]),
             styles: Styles {
                 selected: Style::default().fg(Color::Yellow).modifier(Modifier::BOLD),
+                marked: Style::default().fg(Color::Magenta),
                 normal: Style::default(),
                 header: Style::default().fg(Color::Cyan),
                 subject: Style::default()
                     .fg(Color::Rgb(255, 255, 255))
                     .modifier(Modifier::BOLD),
                 attachment: Style::default().fg(Color::Blue),
+                error: Style::default().fg(Color::Red),
+                highlight: Style::default().fg(Color::Black).bg(Color::Yellow),
+                quotes: vec![
+                    Style::default().fg(Color::Cyan),
+                    Style::default().fg(Color::Green),
+                    Style::default().fg(Color::Magenta),
+                    Style::default().fg(Color::Blue),
+                ],
+                signature: Style::default().fg(Color::DarkGray),
+                diff_file: Style::default().modifier(Modifier::BOLD),
+                diff_hunk: Style::default().fg(Color::Cyan),
+                diff_add: Style::default().fg(Color::Green),
+                diff_remove: Style::default().fg(Color::Red),
+                status: Style::default().modifier(Modifier::REVERSED),
+                tags: tag_styles,
             },
         }
     }
 }
 
-#[allow(dead_code)]
+fn edit_draft(
+    app: &App,
+    draft: &mut Draft,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+) -> Result<(), failure::Error> {
+    debug!("edit_draft");
+
+    let tmp_file = app.tmp_dir.path().join("nutt-new.txt");
+    write_file(&tmp_file, draft.template().as_bytes())?;
+
+    let mut editor = app.config.editor.split_whitespace();
+    let _ = run_interactive(
+        app,
+        terminal,
+        Command::new(editor.next().unwrap_or("nvim"))
+            .args(editor)
+            .arg(&tmp_file),
+    )?;
+
+    draft.update(&std::fs::read_to_string(&tmp_file)?);
+    std::fs::remove_file(tmp_file)?;
+
+    Ok(())
+}
+
 fn compose(
     app: &mut App,
-    _terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
 ) -> Result<(), failure::Error> {
     debug!("compose");
 
-    let mut tmp_file = std::env::temp_dir();
-    tmp_file.push("nutt-new.txt");
+    app.state = AppState::Refresh;
+
+    let identity = app
+        .config
+        .identity("")
+        .ok_or(failure::format_err!("No identity configured"))?;
+    let mut draft = Draft::new(identity);
+    edit_draft(app, &mut draft, terminal)?;
+
+    review_draft(app, draft, terminal)
+}
 
-    let _ = Command::new("nvim")
-        .arg(&tmp_file)
-        .status()
-        .expect("Failed to execute 'nvim'");
+// confirm, sign/encrypt and send edited draft
+fn review_draft(
+    app: &mut App,
+    mut draft: Draft,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+) -> Result<(), failure::Error> {
+    debug!("review_draft");
 
-    let mut body = std::fs::read_to_string(&tmp_file)?;
-    if body.lines().count() == 1 {
-        body.push('\n');
+    // sign/encrypt defaults follow the identity in From
+    if let Some(identity) = app.config.identity(&draft.sender()) {
+        draft.sign = identity.sign;
+        draft.encrypt = identity.encrypt;
     }
 
-    std::fs::remove_file(tmp_file)?;
-    // must include fields: From, Date
-    let email: emailmessage::Message<&str> = Message::builder()
-        .from("Me <me@localhost>".parse().unwrap())
-        .date_now()
-        .to("Me <me@localhost>".parse().unwrap())
-        .subject("<subject>")
-        .body(&body);
+    let mut missing_keys = vec![];
+    let mut check_keys = true;
+    let mut warning: Option<String> = None;
 
-    notmuch::insert_message(format!("{}", email).as_bytes())?;
+    loop {
+        if check_keys {
+            missing_keys = match draft.encrypt {
+                true => compose::missing_keys(&draft, &app.autocrypt),
+                _ => vec![],
+            };
+            check_keys = false;
+        }
 
-    app.state = AppState::Refresh;
+        terminal.draw(|mut f| {
+            let mut headers = vec![];
+            for (header, value) in &[("From", &draft.from), ("To", &draft.to), ("Cc", &draft.cc)] {
+                headers.push(Text::styled(
+                    format!("{}: {}\n", header, value),
+                    app.styles.header,
+                ));
+            }
+            headers.push(Text::styled(
+                format!("Subject: {}\n", draft.subject),
+                app.styles.subject,
+            ));
+            for part in &draft.attachments {
+                headers.push(Text::styled(
+                    format!("Attachment: {} ({})\n", part.name, part.content_type),
+                    app.styles.attachment,
+                ));
+            }
+            headers.push(Text::styled(
+                format!(
+                    "Sign: {}  Encrypt: {}\n",
+                    if draft.sign { "yes" } else { "no" },
+                    if draft.encrypt { "yes" } else { "no" }
+                ),
+                app.styles.attachment,
+            ));
+            let prefer_encrypt = app
+                .config
+                .identity(&draft.sender())
+                .map_or(false, |id| id.encrypt);
+            match app.autocrypt.recommend(&draft.recipients(), prefer_encrypt) {
+                autocrypt::Recommendation::Encrypt if !draft.encrypt => headers.push(Text::styled(
+                    "Autocrypt: encryption recommended\n",
+                    app.styles.selected,
+                )),
+                autocrypt::Recommendation::Available if !draft.encrypt => headers.push(
+                    Text::styled("Autocrypt: encryption available\n", app.styles.normal),
+                ),
+                _ => {}
+            }
+            if !missing_keys.is_empty() {
+                headers.push(Text::styled(
+                    format!("No encryption key for: {}\n", missing_keys.join(", ")),
+                    app.styles.error,
+                ));
+            }
+            if let Some(warning) = &warning {
+                headers.push(Text::styled(format!("{}\n", warning), app.styles.error));
+            }
+
+            let rects = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(1)
+                .constraints(
+                    [
+                        Constraint::Length(headers.len() as u16 + 1),
+                        Constraint::Percentage(100),
+                    ]
+                    .as_ref(),
+                )
+                .split(f.size());
+
+            f.render_widget(
+                Paragraph::new(headers.iter())
+                    .block(Block::default())
+                    .alignment(Alignment::Left)
+                    .wrap(true),
+                rects[0],
+            );
+
+            let body = [Text::raw(draft.body.as_str())];
+            f.render_widget(
+                Paragraph::new(body.iter())
+                    .block(
+                        Block::default()
+                            .borders(Borders::TOP)
+                            .title(" y:send  s:sign  e:encrypt  E:edit  q:abort "),
+                    )
+                    .alignment(Alignment::Left)
+                    .wrap(true),
+                rects[1],
+            );
+        })?;
+
+        match app.input.key() {
+            Ok(Key::Char('y')) => {
+                if draft.recipients().is_empty() {
+                    warning = Some("No recipients".to_string());
+                } else if !missing_keys.is_empty() {
+                    warning = Some("Missing keys, disable encryption with 'e'".to_string());
+                } else {
+                    show_progress(terminal, &AppState::Compose, "Sending...")?;
+                    match compose::build(&draft, &app.config, &app.autocrypt, app.tmp_dir.path())
+                        .and_then(|data| compose::send(&data, &app.config))
+                    {
+                        Ok(_) => {
+                            app.status.info("Message sent");
+                            break;
+                        }
+                        Err(e) => warning = Some(format!("Sending failed: {}", e)),
+                    }
+                }
+            }
+            Ok(Key::Char('s')) => draft.sign = !draft.sign,
+            Ok(Key::Char('e')) => {
+                draft.encrypt = !draft.encrypt;
+                check_keys = true;
+            }
+            Ok(Key::Char('E')) => {
+                edit_draft(app, &mut draft, terminal)?;
+                check_keys = true;
+            }
+            Ok(Key::Char('q')) => {
+                app.status.info("Message discarded");
+                break;
+            }
+            _ => {}
+        }
+    }
 
     Ok(())
 }
@@ -161,54 +576,155 @@ fn refresh_index(app: &mut App) -> Result<(), failure::Error> {
     debug!("refresh_index: {}", &app.search_term);
 
     if app.search_term.is_empty() {
-        app.search_term = "tag:inbox".to_string();
+        app.search_term = app.config.searches[0].query.to_string();
     }
 
-    let messages = notmuch::parse_messages(&app.search_term)?;
+    let mut messages = notmuch::parse_messages(&app.search_term, app.sort.as_str())?;
+    if !app.threaded {
+        match app.sort {
+            config::Sort::NewestFirst => messages.sort_by_key(|m| std::cmp::Reverse(m.timestamp)),
+            config::Sort::OldestFirst => messages.sort_by_key(|m| m.timestamp),
+        }
+    }
+    for msg in &messages {
+        app.autocrypt.update(msg);
+    }
+    // peer state is kept in memory when it can't be saved
+    if let Err(e) = app.autocrypt.save() {
+        warn!("refresh_index: autocrypt: {}", e);
+    }
     app.messages = MessageList::new(messages);
     app.state = AppState::Index;
+    app.status
+        .info(format!("{} message(s)", app.messages.len()));
 
     debug!("refresh_index, count: {}", app.messages.len());
     Ok(())
 }
 
+// status bar: mode, query or latest message, counts and scroll position
+fn render_status(
+    f: &mut Frame<TermionBackend<RawTerminal<Stdout>>>,
+    app: &App,
+    state: &AppState,
+    indicator: &str,
+    area: Rect,
+) {
+    let mut counts = vec![];
+    if !app.messages.marked.is_empty() {
+        counts.push(format!("{} marked", app.messages.marked.len()));
+    }
+    counts.push(format!("{} unread", app.messages.unread()));
+    counts.push(match app.messages.len() {
+        0 => "0/0".to_string(),
+        len => format!("{}/{}", app.messages.selected + 1, len),
+    });
+    counts.push(indicator.to_string());
+    let counts = format!(" {} ", counts.join("  "));
+    let counts_width = std::cmp::min(width::width(&counts) as u16, area.width);
+
+    let text = match app.status.transient() {
+        Some(entry) if entry.level == status::Level::Error => {
+            Text::styled(format!(" {}", entry.text), app.styles.error)
+        }
+        Some(entry) => Text::raw(format!(" {}", entry.text)),
+        None => Text::raw(format!(" {}", app.search_term)),
+    };
+    f.render_widget(
+        Paragraph::new(
+            [
+                Text::styled(format!(" {} ", state.name()), app.styles.status),
+                text,
+            ]
+            .iter(),
+        ),
+        Rect::new(area.x, area.y, area.width - counts_width, 1),
+    );
+    f.render_widget(
+        Paragraph::new([Text::raw(counts)].iter()).alignment(Alignment::Right),
+        Rect::new(area.right() - counts_width, area.y, counts_width, 1),
+    );
+}
+
+// status bar written at once with the running job, jobs block input
+// so their result is shown as a status message on the next frame
+fn show_progress(
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    state: &AppState,
+    text: &str,
+) -> Result<(), failure::Error> {
+    let size = terminal.size()?;
+    let name = format!(" {} ", state.name());
+    write!(
+        terminal.backend_mut(),
+        "{}{}{}{}{} {}",
+        Goto(2, size.height),
+        termion::clear::CurrentLine,
+        termion::style::Invert,
+        name,
+        termion::style::NoInvert,
+        width::truncate(text, (size.width as usize).saturating_sub(name.len() + 4))
+    )?;
+    terminal.backend_mut().flush()?;
+
+    Ok(())
+}
+
 // TODO: refactor/split to smaller functions
-// TODO: simplify scrolling logic which is awful now
 fn show_index(
     app: &mut App,
     terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
 ) -> Result<(), failure::Error> {
     debug!("show_index, count: {}", app.messages.len());
 
-    let mut is_input = false;
-    let input = &mut String::new();
-    let mut scroll = 0;
+    let pieces = format::parse(&app.config.index_format);
+    let mut prompt: Option<(Prompt, Bulk)> = None;
+    // search query being edited
+    let mut search: Option<Prompt> = None;
+    // message rows on screen, for mouse clicks
+    let mut rows_area = Rect::default();
+    let mut clicks = mouse::Clicks::default();
+    let mut viewport = scroll::Viewport::default();
 
     loop {
         terminal.hide_cursor()?;
         terminal.draw(|mut f| {
-            let view_height = f.size().height - 5;
-
-            if app.messages.selected < scroll {
-                scroll -= scroll - app.messages.selected;
-            } else if app.messages.selected - scroll >= view_height {
-                scroll = app.messages.selected + 1 - view_height;
-            }
+            let view_height = f.size().height.saturating_sub(6);
+            viewport.resize(app.messages.len(), view_height);
+            viewport.show(app.messages.selected);
 
             let rects = Layout::default()
                 .direction(Direction::Vertical)
                 .horizontal_margin(1)
-                .constraints([Constraint::Length(3), Constraint::Percentage(100)].as_ref())
+                .constraints(
+                    [
+                        Constraint::Length(3),
+                        Constraint::Min(0),
+                        Constraint::Length(1),
+                    ]
+                    .as_ref(),
+                )
                 .split(f.size());
 
-            let (input_style, search_text) = match is_input {
-                true => (app.styles.selected, Text::Raw(input.as_str().into())),
-                _ => (
+            let (input_style, search_text) = match &search {
+                Some(p) => (app.styles.selected, Text::Raw(p.input().into())),
+                None => (
                     app.styles.normal,
                     Text::Raw(app.search_term.as_str().into()),
                 ),
             };
 
+            // saved search name and sort order
+            let title = match app
+                .config
+                .searches
+                .iter()
+                .find(|s| s.query == app.search_term)
+            {
+                Some(search) => format!("{} ({})", search.name, app.sort.as_str()),
+                None => format!("({})", app.sort.as_str()),
+            };
+
             // render inputbox
             f.render_widget(
                 Paragraph::new([search_text].iter())
@@ -216,7 +732,8 @@ fn show_index(
                     .block(
                         Block::default()
                             .borders(Borders::ALL)
-                            .border_style(input_style),
+                            .border_style(input_style)
+                            .title(&title),
                     )
                     .alignment(Alignment::Left)
                     .wrap(true),
@@ -224,81 +741,192 @@ fn show_index(
             );
 
             // format index rows
-            let rows = app
+            let subjects = match app.threaded {
+                true => thread_subjects(&app.messages.list),
+                _ => app
+                    .messages
+                    .list
+                    .iter()
+                    .map(|m| format_subject(m.headers.get("Subject")))
+                    .collect(),
+            };
+            let mut counts: HashMap<usize, usize> = HashMap::new();
+            for m in &app.messages.list {
+                *counts.entry(m.thread).or_insert(0) += 1;
+            }
+
+            rows_area = Rect::new(rects[1].x, rects[1].y + 1, rects[1].width, view_height);
+            let mut rows = vec![Text::styled(
+                format!("{}\n", format::header(&pieces)),
+                app.styles.header,
+            )];
+            for (i, (m, subject)) in app
                 .messages
                 .list
                 .iter()
-                .skip(scroll as usize)
-                .map(|m| {
-                    vec![
-                        m.date_relative.to_string(),
-                        m.headers.get("From").unwrap_or(&"n/a".into()).to_string(),
-                        format_subject(m.headers.get("Subject"), m.depth),
-                        Tags(&m.tags).to_string(),
-                    ]
-                })
+                .zip(subjects.iter())
                 .enumerate()
-                .map(
-                    |(i, item)| match (is_input, i as u16 + scroll == app.messages.selected) {
-                        (false, true) => Row::StyledData(item.into_iter(), app.styles.selected),
-                        _ => Row::StyledData(item.into_iter(), app.styles.normal),
-                    },
+                .skip(viewport.offset as usize)
+                .take(view_height as usize)
+            {
+                let selected = search.is_none() && i as u16 == app.messages.selected;
+                let style = match (selected, app.messages.is_marked(m)) {
+                    (true, _) => app.styles.selected,
+                    (false, true) => app.styles.marked,
+                    _ => app.styles.normal,
+                };
+                let row = format::render_styled(&pieces, style, |field| {
+                    let text = match field {
+                        format::Field::Date(None) => m.date_relative.to_string(),
+                        format::Field::Date(Some(fmt)) => Local
+                            .timestamp_opt(m.timestamp as i64, 0)
+                            .single()
+                            .map(|date| date.format(fmt).to_string())
+                            .unwrap_or_default(),
+                        format::Field::From => {
+                            m.headers.get("From").unwrap_or(&"n/a".into()).to_string()
+                        }
+                        format::Field::Subject => subject.to_string(),
+                        // selected row keeps its own style
+                        format::Field::Tags if !selected => {
+                            return tag_spans(app, &m.tags, "index", style)
+                        }
+                        format::Field::Tags => tag_spans(app, &m.tags, "index", style)
+                            .into_iter()
+                            .map(|(text, _)| text)
+                            .collect(),
+                        format::Field::Attachment => match notmuch::has_attachments(&m.body) {
+                            true => "@".to_string(),
+                            _ => " ".to_string(),
+                        },
+                        format::Field::Size => m.size.map(format::size).unwrap_or_default(),
+                        format::Field::Count => counts.get(&m.thread).unwrap_or(&1).to_string(),
+                    };
+                    vec![(text, style)]
+                });
+                rows.extend(
+                    row.into_iter()
+                        .map(|(text, style)| Text::styled(text, style)),
                 );
+                rows.push(Text::raw("\n"));
+            }
 
             // render index
             f.render_widget(
-                Table::new(["Date", "From", "Subject", "Tags"].iter(), rows)
-                    .column_spacing(2)
-                    .header_style(app.styles.header)
-                    .block(
-                        Block::default().borders(Borders::BOTTOM | Borders::LEFT | Borders::RIGHT),
-                    )
-                    .header_gap(0)
-                    .widths(&[
-                        Constraint::Length(12),
-                        Constraint::Length(20),
-                        Constraint::Percentage(40),
-                        Constraint::Percentage(30),
-                    ]),
+                Paragraph::new(rows.iter()).block(
+                    Block::default().borders(Borders::BOTTOM | Borders::LEFT | Borders::RIGHT),
+                ),
                 rects[1],
             );
+
+            // render prompt or status on the last line
+            match &prompt {
+                Some((prompt, _)) => f.render_widget(
+                    Paragraph::new([Text::styled(prompt.text(), app.styles.selected)].iter()),
+                    rects[2],
+                ),
+                None => render_status(&mut f, app, &app.state, &viewport.indicator(), rects[2]),
+            }
         })?;
 
+        if let Some((mut p, mut action)) = prompt.take() {
+            let y = terminal.size()?.bottom().saturating_sub(1);
+            terminal.show_cursor()?;
+            terminal.set_cursor(1 + p.cursor(), y)?;
+
+            let event = app.input.event()?;
+            if let (Bulk::Pipe(mode), Event::Key(key)) = (&mut action, &event) {
+                if !p.is_pasting() && pipe::handle_key(key, &mut p, mode, &mut app.pipe_history) {
+                    prompt = Some((p, action));
+                    continue;
+                }
+            }
+            match p.handle_event(event, &mut app.kill_ring) {
+                PromptEvent::Submit(input) => bulk_prompt(app, terminal, action, &input)?,
+                PromptEvent::Cancel => {}
+                PromptEvent::Edit => prompt = Some((p, action)),
+            }
+            continue;
+        }
+
         // handle input
-        if is_input {
+        if let Some(mut p) = search.take() {
             terminal.show_cursor()?;
-            write!(
-                terminal.backend_mut(),
-                "{}",
-                Goto(3 + input.len() as u16, 2)
-            )?;
+            write!(terminal.backend_mut(), "{}", Goto(3 + p.cursor(), 2))?;
             io::stdout().flush().ok();
 
-            match io::stdin().keys().next().unwrap() {
-                Ok(Key::Char('\n')) => {
-                    app.search_term = input.to_string();
-                    input.clear();
+            // redraw when the status message expires
+            if !app.input.wait(app.status.expires_in()) {
+                search = Some(p);
+                continue;
+            }
+            match p.handle_event(app.input.event()?, &mut app.kill_ring) {
+                PromptEvent::Submit(input) => {
+                    app.search_term = input;
                     app.state = AppState::Refresh;
                     break;
                 }
-                Ok(Key::Backspace) => {
-                    let _ = input.pop();
-                }
-                Ok(Key::Esc) => is_input = false,
-                Ok(Key::Char(ch)) => (*input).push(ch),
-                _ => {}
+                PromptEvent::Cancel => {}
+                PromptEvent::Edit => search = Some(p),
             }
         } else {
-            match io::stdin().keys().next().unwrap() {
+            if !app.input.wait(app.status.expires_in()) {
+                continue;
+            }
+            let key = match app.input.event() {
+                Ok(Event::Key(key)) => Ok(key),
+                Ok(Event::Mouse(MouseEvent::Press(button, _, y))) => {
+                    match button {
+                        MouseButton::WheelDown => {
+                            for _ in 0..mouse::WHEEL_LINES {
+                                app.messages.select_next();
+                            }
+                        }
+                        MouseButton::WheelUp => {
+                            for _ in 0..mouse::WHEEL_LINES {
+                                app.messages.select_prev();
+                            }
+                        }
+                        // mouse rows are 1-based
+                        MouseButton::Left if y > rows_area.y && y <= rows_area.bottom() => {
+                            let row = y - 1 - rows_area.y + viewport.offset;
+                            if row < app.messages.len() {
+                                app.messages.selected = row;
+                                if clicks.click(row) {
+                                    app.state = AppState::View;
+                                    break;
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(e) => Err(e),
+            };
+            // page keys take the selection along
+            if let Ok(key) = &key {
+                if let Some(lines) = viewport.page_key(key) {
+                    app.messages.select_by(lines);
+                    continue;
+                }
+            }
+            match key {
                 Ok(Key::Down) | Ok(Key::Char('j')) => app.messages.select_next(),
                 Ok(Key::Up) | Ok(Key::Char('k')) => app.messages.select_prev(),
-                Ok(Key::Char('g')) => match io::stdin().keys().next().unwrap() {
+                Ok(Key::Char('g')) => match app.input.key() {
                     Ok(Key::Char('g')) => {
                         app.messages.select_first();
                     }
                     _ => {}
                 },
                 Ok(Key::Char('G')) => app.messages.select_last(),
+                Ok(Key::Char('z')) => {
+                    if let Ok(Key::Char(ch)) = app.input.key() {
+                        viewport.align(app.messages.selected, ch);
+                    }
+                }
                 Ok(Key::Char('q')) => {
                     app.state = AppState::Exit;
                     break;
@@ -309,11 +937,82 @@ fn show_index(
                     }
                     break;
                 }
+                Ok(Key::Char('c')) => {
+                    if !app.messages.list.is_empty() {
+                        app.state = AppState::Thread;
+                    }
+                    break;
+                }
                 Ok(Key::Char('m')) => {
                     app.state = AppState::Compose;
                     break;
                 }
-                Ok(Key::Char('l')) => is_input = true,
+                Ok(Key::Char('T')) => {
+                    let id = app.messages.get_selected().map(|m| m.id.to_string());
+                    app.threaded = !app.threaded;
+                    show_progress(terminal, &app.state, "Searching...")?;
+                    if let Err(e) = refresh_index(app) {
+                        // index still shows the old order
+                        app.threaded = !app.threaded;
+                        app.status.error(format!("Search failed: {}", e));
+                    }
+                    terminal.clear()?;
+                    if let Ok(id) = id {
+                        app.messages.select_id(&id);
+                    }
+                }
+                Ok(Key::Char('o')) => {
+                    app.sort = app.sort.toggle();
+                    app.state = AppState::Refresh;
+                    break;
+                }
+                Ok(Key::Char(ch @ '1'..='9')) => {
+                    if let Some(search) = app.config.searches.get(ch as usize - '1' as usize) {
+                        app.search_term = search.query.to_string();
+                        app.sort = search.sort;
+                        app.state = AppState::Refresh;
+                        break;
+                    }
+                }
+                Ok(Key::Char('l')) => search = Some(Prompt::new("", &app.search_term)),
+                Ok(Key::Char(' ')) => {
+                    app.messages.toggle_mark();
+                    app.messages.select_next();
+                }
+                Ok(Key::Char('t')) => app.messages.mark_thread(),
+                Ok(Key::Char('V')) => {
+                    app.messages.mark_range();
+                    if app.messages.anchor.is_some() {
+                        app.status.info("Range start set, V again to mark");
+                    }
+                }
+                Ok(Key::Char('M')) => {
+                    prompt = Some((Prompt::new("Mark matching: ", ""), Bulk::Mark))
+                }
+                Ok(Key::Char('U')) => app.messages.clear_marks(),
+                Ok(Key::Char('+')) => prompt = Some((Prompt::new("Tag: ", ""), Bulk::Tag)),
+                Ok(Key::Char('a')) => {
+                    let result = bulk_tag(app, &["-inbox"]);
+                    app.status.report(result);
+                }
+                Ok(Key::Char('d')) => {
+                    let result = bulk_tag(app, &["+deleted", "-inbox"]);
+                    app.status.report(result);
+                }
+                Ok(Key::Char('|')) => {
+                    let mode = pipe::Mode::Raw;
+                    prompt = Some((Prompt::new(&mode.label(), ""), Bulk::Pipe(mode)));
+                }
+                Ok(Key::Char('S')) => {
+                    let dir = attachment::default_dir(app.config.attachment_dir.as_deref());
+                    prompt = Some((Prompt::new("Save attachments to: ", &dir), Bulk::Save));
+                }
+                Ok(Key::Char('f')) => {
+                    if let Err(e) = forward(app, terminal) {
+                        app.status.error(format!("Forward failed: {}", e));
+                    }
+                }
+                Ok(Key::Char(':')) => prompt = Some((Prompt::new(":", ""), Bulk::Command)),
                 _ => {}
             }
         }
@@ -322,75 +1021,636 @@ fn show_index(
     Ok(())
 }
 
-fn format_subject(subject: Option<&String>, depth: usize) -> String {
-    //    debug!("format_subject: {:?} {}", &subject, &depth);
-
-    let mut prefix = String::from("");
-    for _ in 0..depth {
-        prefix.push_str("  ");
-    }
-    format!(
-        "{}{}",
-        prefix,
-        subject.unwrap_or(&"<no subject>".to_string())
-    )
+// index actions asking for input first
+enum Bulk {
+    Mark,
+    Tag,
+    Pipe(pipe::Mode),
+    Save,
+    Command,
 }
 
-fn format_headers<'a>(
-    app: &App,
-    msg: &notmuch::Message,
-    atts: &[notmuch::Attachment],
-) -> Vec<Text<'a>> {
-    debug!("format_headers");
-
-    let mut headers = vec![];
-    for (header, style, default) in &[
-        ("From", app.styles.header, "".to_string()),
-        ("To", app.styles.header, "".to_string()),
-        ("Date", app.styles.header, "".to_string()),
-        ("Attachments", app.styles.attachment, atts.len().to_string()),
-        ("Subject", app.styles.subject, "".to_string()),
-    ] {
-        headers.push(Text::styled(
-            format!(
-                "{}: {}\n",
-                header,
-                msg.headers.get(*header).unwrap_or(&default.to_string())
-            ),
-            *style,
-        ));
+// apply tag changes to marked or selected messages
+fn bulk_tag<S: AsRef<str>>(app: &mut App, changes: &[S]) -> Result<String, failure::Error> {
+    let changes: Vec<String> = changes.iter().map(|c| c.as_ref().to_string()).collect();
+    if changes.is_empty()
+        || changes
+            .iter()
+            .any(|c| c.len() < 2 || !(c.starts_with('+') || c.starts_with('-')))
+    {
+        failure::bail!("Tag changes must be like '+tag' or '-tag'");
     }
 
-    headers
+    let ids = app.messages.targets();
+    notmuch::tag(&ids, &changes).map_err(|e| failure::format_err!("Tagging failed: {}", e))?;
+    app.messages.apply_tags(&ids, &changes);
+    app.messages.clear_marks();
+
+    Ok(format!("{} {} message(s)", changes.join(" "), ids.len()))
 }
 
-// TODO: refactor/split to smaller functions
-fn view_selected(
+fn bulk_prompt(
     app: &mut App,
     terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    action: Bulk,
+    input: &str,
 ) -> Result<(), failure::Error> {
-    debug!("view_selected");
+    debug!("bulk_prompt: {}", input);
+
+    match action {
+        Bulk::Mark => {
+            let query = format!("({}) and ({})", app.search_term, input);
+            match notmuch::search_ids(&query) {
+                Ok(ids) => {
+                    let count = app.messages.mark_ids(&ids);
+                    app.status.info(format!("Marked {} message(s)", count));
+                }
+                Err(e) => app.status.error(format!("Search failed: {}", e)),
+            }
+        }
+        Bulk::Tag => {
+            let result = bulk_tag(app, &input.split_whitespace().collect::<Vec<&str>>());
+            app.status.report(result);
+        }
+        Bulk::Pipe(mode) => {
+            app.pipe_history.add(input);
+            match pipe_messages(app, terminal, &app.messages.targets(), mode, input)? {
+                Some(error) => app.status.error(error),
+                None => app.messages.clear_marks(),
+            }
+        }
+        Bulk::Save => {
+            let result = save_marked(app, terminal, input);
+            // progress was written over the frame
+            terminal.clear()?;
+            app.status.report(result);
+        }
+        Bulk::Command => {
+            if let Some(error) = run_command(app, terminal, input)? {
+                app.status.error(error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// attachments of marked or selected messages saved to dir
+fn save_marked(
+    app: &mut App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    dir: &str,
+) -> Result<String, failure::Error> {
+    debug!("save_marked: {}", dir);
+
+    let dir = attachment::expand_dir(dir);
+    let targets = app.messages.targets();
+    let mut count = 0;
+    for (i, id) in targets.iter().enumerate() {
+        let msg = match app.messages.list.iter().find(|m| m.id == *id) {
+            Some(msg) => msg,
+            None => continue,
+        };
+        show_progress(
+            terminal,
+            &app.state,
+            &format!("Saving attachments {}/{}", i + 1, targets.len()),
+        )?;
+        let (_, atts) = notmuch::body_attachments(&msg.id, &msg.body)?;
+        for att in atts
+            .iter()
+            .filter(|att| matches!(att, notmuch::Attachment::File(..)))
+        {
+            attachment::save(&msg.id, att, &dir)
+                .map_err(|e| failure::format_err!("Saving failed: {}", e))?;
+            count += 1;
+        }
+    }
+    app.messages.clear_marks();
+
+    Ok(format!(
+        "Saved {} attachment(s) to {}",
+        count,
+        dir.display()
+    ))
+}
+
+// ':' commands, error message on failure
+fn run_command(
+    app: &App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    input: &str,
+) -> Result<Option<String>, failure::Error> {
+    debug!("run_command: {}", input);
+
+    match input.trim() {
+        "" => {}
+        "messages" | "mes" => show_pager(app, terminal, "Messages", &app.status.log())?,
+        command => return Ok(Some(format!("Unknown command: {}", command))),
+    }
+
+    Ok(None)
+}
+
+// data piped in 'mode' for messages 'ids'
+fn pipe_data(app: &App, ids: &[String], mode: pipe::Mode) -> Result<Vec<u8>, failure::Error> {
+    match mode {
+        pipe::Mode::Raw if ids.len() == 1 => notmuch::raw_message(&ids[0]),
+        pipe::Mode::Raw => notmuch::mbox(ids),
+        pipe::Mode::Decoded => {
+            let mut result = String::new();
+            for msg in app.messages.list.iter().filter(|m| ids.contains(&m.id)) {
+                let (body, _) = message_text(app, msg, 80)?;
+                result.push_str(&body);
+            }
+            Ok(result.into_bytes())
+        }
+        pipe::Mode::Thread => {
+            let threads: HashSet<usize> = app
+                .messages
+                .list
+                .iter()
+                .filter(|m| ids.contains(&m.id))
+                .map(|m| m.thread)
+                .collect();
+            let ids: Vec<String> = app
+                .messages
+                .list
+                .iter()
+                .filter(|m| threads.contains(&m.thread))
+                .map(|m| m.id.to_string())
+                .collect();
+            notmuch::mbox(&ids)
+        }
+    }
+}
+
+// pipe messages to command and show output in pager, error message on failure
+fn pipe_messages(
+    app: &App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    ids: &[String],
+    mode: pipe::Mode,
+    command: &str,
+) -> Result<Option<String>, failure::Error> {
+    debug!("pipe_messages: {:?} {}", mode, command);
+
+    let output = pipe_data(app, ids, mode).and_then(|data| pipe::run(command, &data));
+    match output {
+        Ok((output, status)) => {
+            let title = format!("{} [{}]", command, status);
+            show_pager(
+                app,
+                terminal,
+                &title,
+                &format!("{}\n[{}]\n", output, status),
+            )?;
+            Ok(None)
+        }
+        Err(e) => Ok(Some(format!("Pipe failed: {}", e))),
+    }
+}
+
+// forward marked or selected messages as attachments
+fn forward(
+    app: &mut App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+) -> Result<(), failure::Error> {
+    debug!("forward");
+
+    let ids = app.messages.targets();
+    let identity = app
+        .config
+        .identity("")
+        .ok_or(failure::format_err!("No identity configured"))?;
+    let mut draft = Draft::new(identity);
+
+    for id in &ids {
+        let subject = app
+            .messages
+            .list
+            .iter()
+            .find(|m| m.id == *id)
+            .and_then(|m| m.headers.get("Subject"))
+            .map(|s| s.to_string())
+            .unwrap_or_default();
+        if draft.subject.is_empty() {
+            draft.subject = format!("Fwd: {}", subject);
+        }
+        draft.attachments.push(compose::Part {
+            name: attachment::sanitize_filename(&format!("{}.eml", subject)),
+            content_type: "message/rfc822".to_string(),
+            data: notmuch::raw_message(id)?,
+        });
+    }
+
+    edit_draft(app, &mut draft, terminal)?;
+    review_draft(app, draft, terminal)?;
+    app.messages.clear_marks();
+
+    Ok(())
+}
+
+// tags shown in 'view' following the display rules, with their styles
+fn tag_spans(app: &App, tags: &[String], view: &str, base: Style) -> Vec<(String, Style)> {
+    let mut result = vec![];
+
+    for tag in tags {
+        let rule = app.config.tags.get(tag);
+        if rule.map_or(false, |r| r.hide.iter().any(|v| v == view)) {
+            continue;
+        }
+        if !result.is_empty() {
+            result.push((" ".to_string(), base));
+        }
+        let text = rule
+            .and_then(|r| r.symbol.as_ref())
+            .unwrap_or(tag)
+            .to_string();
+        result.push((text, *app.styles.tags.get(tag).unwrap_or(&base)));
+    }
+
+    result
+}
+
+fn format_subject(subject: Option<&String>) -> String {
+    subject.unwrap_or(&"<no subject>".to_string()).to_string()
+}
+
+const SUBJECT_PREFIXES: [&str; 3] = ["re", "fwd", "fw"];
+
+// subject without reply and forward prefixes like "Re:", "Fwd:" and
+// "Re[2]:" or list tags like "[list]", for comparing thread members
+fn base_subject(subject: &str) -> String {
+    let mut result = subject.trim();
+    loop {
+        if result.starts_with('[') {
+            match result.find(']') {
+                Some(end) => result = result[end + 1..].trim_start(),
+                None => break,
+            }
+            continue;
+        }
+        let prefix = match result.find(':') {
+            Some(pos) => &result[..pos],
+            None => break,
+        };
+        // reply counters like Re[2]: or Re(2):
+        let word = prefix.trim_end_matches(|c: char| {
+            c.is_ascii_digit() || c == '[' || c == ']' || c == '(' || c == ')'
+        });
+        if !SUBJECT_PREFIXES
+            .iter()
+            .any(|p| word.trim_end().eq_ignore_ascii_case(p))
+        {
+            break;
+        }
+        result = result[prefix.len() + 1..].trim_start();
+    }
+    result.to_lowercase()
+}
+
+// subject column with thread tree glyphs, messages in thread order,
+// subjects repeating the parent's are left out
+fn thread_subjects(messages: &[notmuch::Message]) -> Vec<String> {
+    // whether a later sibling follows, scanning backwards
+    let mut has_next = vec![false; messages.len()];
+    let mut seen: Vec<bool> = vec![];
+    for (i, msg) in messages.iter().enumerate().rev() {
+        if i + 1 < messages.len() && messages[i + 1].thread != msg.thread {
+            seen.clear();
+        }
+        seen.resize(msg.depth + 1, false);
+        has_next[i] = seen[msg.depth];
+        seen[msg.depth] = true;
+    }
+
+    let mut result = vec![];
+    let mut open: Vec<bool> = vec![];
+    let mut parents: Vec<String> = vec![];
+    for (i, msg) in messages.iter().enumerate() {
+        let subject = format_subject(msg.headers.get("Subject"));
+        let base = base_subject(&subject);
+
+        let mut prefix = String::new();
+        if msg.depth > 0 {
+            for level in 1..msg.depth {
+                match open.get(level) {
+                    Some(true) => prefix.push_str("\u{2502} "),
+                    _ => prefix.push_str("  "),
+                }
+            }
+            match has_next[i] {
+                true => prefix.push_str("\u{251c}\u{2500}"),
+                _ => prefix.push_str("\u{2514}\u{2500}"),
+            }
+        }
+        open.resize(msg.depth + 1, false);
+        open[msg.depth] = has_next[i];
+
+        parents.truncate(msg.depth);
+        let repeated = msg.depth > 0 && parents.last() == Some(&base);
+        parents.push(base);
+
+        match repeated {
+            true => result.push(prefix),
+            _ => result.push(format!("{}{}", prefix, subject)),
+        }
+    }
+
+    result
+}
+
+fn format_headers<'a>(
+    app: &App,
+    msg: &notmuch::Message,
+    atts: &[notmuch::Attachment],
+) -> Vec<Text<'a>> {
+    debug!("format_headers");
+
+    let mut headers = vec![];
+    for (header, style, default) in &[
+        ("From", app.styles.header, "".to_string()),
+        ("To", app.styles.header, "".to_string()),
+        ("Date", app.styles.header, "".to_string()),
+        ("Attachments", app.styles.attachment, atts.len().to_string()),
+        ("Subject", app.styles.subject, "".to_string()),
+        ("Tags", app.styles.header, Tags(&msg.tags).to_string()),
+    ] {
+        headers.push(Text::styled(
+            format!(
+                "{}: {}\n",
+                header,
+                msg.headers.get(*header).unwrap_or(&default.to_string())
+            ),
+            *style,
+        ));
+    }
+
+    headers
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ViewMode {
+    Normal,
+    // every header notmuch returns
+    Headers,
+    Raw,
+}
+
+enum ViewPrompt {
+    // selected attachment or all when None
+    Save(Option<usize>),
+    Pipe(pipe::Mode),
+    // search backwards when true
+    Search(bool),
+    Command,
+    // invitation reply with participation status, sent after 'y'
+    Reply(&'static str),
+}
+
+// diffstat and style of each line when text contains a diff
+fn diff_styles(app: &App, lines: &[&str]) -> Option<(Vec<String>, Vec<Option<Style>>)> {
+    let kinds = diff::classify(lines);
+    if kinds.iter().all(|k| k.is_none()) {
+        return None;
+    }
+
+    let styles = kinds
+        .iter()
+        .map(|kind| {
+            kind.map(|kind| match kind {
+                diff::Kind::File => app.styles.diff_file,
+                diff::Kind::Hunk => app.styles.diff_hunk,
+                diff::Kind::Add => app.styles.diff_add,
+                diff::Kind::Remove => app.styles.diff_remove,
+                diff::Kind::Context => app.styles.normal,
+            })
+        })
+        .collect();
+
+    Some((diff::diffstat(lines, &kinds), styles))
+}
+
+fn is_patch(filename: &str, content_type: &str) -> bool {
+    let filename = filename.to_lowercase();
+    filename.ends_with(".patch")
+        || filename.ends_with(".diff")
+        || ["text/x-diff", "text/x-patch", "text/x-diff-file"].contains(&content_type)
+}
+
+// wrapped body lines with style and the foldable block they belong to,
+// folded blocks are shown as a one line summary, diffs get a diffstat on top
+fn body_rows(
+    app: &App,
+    source: &[&str],
+    blocks: &[quote::Block],
+    folded: &[bool],
+    width: u16,
+) -> Vec<(String, Style, Option<usize>)> {
+    let block_style = |block: &quote::Block| match block.kind {
+        quote::Kind::Quote(level) => app.styles.quotes[(level - 1) % app.styles.quotes.len()],
+        quote::Kind::Signature => app.styles.signature,
+    };
+
+    let mut result = vec![];
+    let diff_styles = diff_styles(app, source);
+    if let Some((stat, _)) = &diff_styles {
+        for line in stat {
+            result.push((line.to_string(), app.styles.header, None));
+        }
+        result.push((String::new(), app.styles.normal, None));
+    }
+
+    for (i, line) in source.iter().enumerate() {
+        let containing = || {
+            blocks
+                .iter()
+                .enumerate()
+                .filter(move |(_, b)| b.start <= i && i < b.end)
+        };
+
+        // outer levels come first
+        if let Some((n, block)) = containing().find(|(n, _)| folded[*n]) {
+            if i == block.start {
+                result.push((block.summary(), block_style(block), Some(n)));
+            }
+            continue;
+        }
+
+        let inner = containing().next_back();
+        let diff_style = diff_styles.as_ref().and_then(|(_, styles)| styles[i]);
+        let style = match (inner, diff_style) {
+            (Some((_, b)), _) => block_style(b),
+            (None, Some(style)) => style,
+            (None, None) => app.styles.normal,
+        };
+        for part in wrap_line(line, width as usize) {
+            result.push((part, style, inner.map(|(n, _)| n)));
+        }
+    }
+
+    result
+}
+
+// matched line to scroll to from 'scroll', wraps around the ends
+fn next_match(matches: &[u16], scroll: u16, backward: bool, inclusive: bool) -> Option<u16> {
+    let found = match (backward, inclusive) {
+        (false, true) => matches.iter().find(|l| **l >= scroll),
+        (false, false) => matches.iter().find(|l| **l > scroll),
+        (true, true) => matches.iter().rev().find(|l| **l <= scroll),
+        (true, false) => matches.iter().rev().find(|l| **l < scroll),
+    };
+    match (found, backward) {
+        (Some(line), _) => Some(*line),
+        (None, false) => matches.first().cloned(),
+        (None, true) => matches.last().cloned(),
+    }
+}
+
+// line split to styled parts at byte ranges
+fn highlight<'a>(
+    line: &str,
+    ranges: &[(usize, usize)],
+    normal: Style,
+    style: Style,
+) -> Vec<Text<'a>> {
+    let mut result = vec![];
+    let mut pos = 0;
+
+    for (start, end) in ranges {
+        if *start > pos {
+            result.push(Text::styled(line[pos..*start].to_string(), normal));
+        }
+        result.push(Text::styled(line[*start..*end].to_string(), style));
+        pos = *end;
+    }
+    if pos < line.len() {
+        result.push(Text::styled(line[pos..].to_string(), normal));
+    }
+
+    result
+}
+
+// common headers first, then the rest alphabetically
+fn all_headers(msg: &notmuch::Message) -> String {
+    let order = ["From", "To", "Cc", "Bcc", "Reply-To", "Date", "Subject"];
+    let mut names: Vec<&String> = msg.headers.keys().collect();
+    names.sort_by_key(|name| {
+        (
+            order.iter().position(|o| o == name).unwrap_or(order.len()),
+            name.to_string(),
+        )
+    });
+
+    let mut result = String::new();
+    for name in names {
+        result.push_str(&format!("{}: {}\n", name, msg.headers[name]));
+    }
+    result.push_str(&format!("Tags: {}\n", Tags(&msg.tags)));
+    for filename in &msg.filename {
+        result.push_str(&format!("File: {}\n", filename));
+    }
+
+    result
+}
+
+// TODO: refactor/split to smaller functions
+fn view_selected(
+    app: &mut App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+) -> Result<(), failure::Error> {
+    debug!("view_selected");
 
     app.state = AppState::Index;
 
     let msg = app.messages.get_selected()?;
 
-    let (body, atts) = notmuch::body_attachments(&msg.body)?;
-    let headers = format_headers(&app, &msg, &atts);
+    let (mut body, atts) = notmuch::body_attachments(&msg.id, &msg.body)?;
+    let mut headers = format_headers(app, msg, &atts);
+    let event = invitation(&msg.id, &atts);
+    if let Some(event) = &event {
+        headers.extend(format_invitation(app, event));
+    }
 
-    let body_len = body.lines().count() as u16;
-    let content_len = body_len + atts.len() as u16;
-    let body_text = vec![Text::Raw(body.into())];
-    let (mut scroll, mut scroll_max) = (0, 0);
-    let headers_len = headers.len() as u16;
+    let html_part = atts.iter().find_map(|att| match att {
+        notmuch::Attachment::Html(html, _) => Some(html.as_str()),
+        _ => None,
+    });
+    // html only messages are rendered to the view width
+    let html = match body.is_empty() {
+        true => html_part,
+        _ => None,
+    };
+    let mut body_width = 0;
+
+    let mut viewport = scroll::Viewport::default();
+    // last search match, what zz/zt/zb align when set
+    let mut match_line: Option<u16> = None;
     let mut selected_att: Option<usize> = None;
+    // first attachment line on screen, for mouse clicks
+    let mut atts_top = 0;
+    let mut prompt: Option<(Prompt, ViewPrompt)> = None;
+    let mut mode = ViewMode::Normal;
+    let mut raw: Option<String> = None;
+    // query terms are highlighted until searching
+    let mut patterns = search::query_terms(&app.search_term);
+    let mut backward = false;
+    let mut folded: Vec<bool> = vec![];
 
     loop {
-        terminal.draw(|mut f| {
-            let view_height = f.size().height - headers_len - 4;
-            if content_len > view_height {
-                scroll_max = content_len - view_height;
+        let width = terminal.size()?.width.saturating_sub(2);
+        if let Some(html) = &html {
+            if width != body_width {
+                body = notmuch::html_to_text(html, width, app.config.html_renderer.as_deref())?;
+                body_width = width;
             }
+        }
+        // all headers and raw source are scrolled with the body
+        let (headers, shown) = match (mode, &raw) {
+            (ViewMode::Headers, _) => (
+                vec![Text::styled(
+                    "All headers  (h: raw source)\n",
+                    app.styles.header,
+                )],
+                format!("{}\n{}", all_headers(msg), body),
+            ),
+            (ViewMode::Raw, Some(raw)) => (
+                vec![Text::styled(
+                    "Raw source  (h: normal view)\n",
+                    app.styles.header,
+                )],
+                raw.to_string(),
+            ),
+            _ => (headers.clone(), body.to_string()),
+        };
+        let headers_len = headers.len() as u16;
+
+        // quotes and signature fold in the normal view only
+        let source: Vec<&str> = shown.lines().collect();
+        let blocks = match mode {
+            ViewMode::Normal => quote::blocks(&source),
+            _ => vec![],
+        };
+        if folded.len() != blocks.len() {
+            folded = quote::default_folded(&source, &blocks);
+        }
+
+        // wrapped here so scroll positions match lines
+        let rows = body_rows(app, &source, &blocks, &folded, width);
+        let found: Vec<Vec<(usize, usize)>> = rows
+            .iter()
+            .map(|(line, _, _)| search::find(line, &patterns))
+            .collect();
+        let body_len = rows.len() as u16;
+        let mut body_text = vec![];
+        for ((line, style, _), ranges) in rows.iter().zip(found.iter()) {
+            body_text.extend(highlight(line, ranges, *style, app.styles.highlight));
+            body_text.push(Text::raw("\n"));
+        }
+
+        terminal.draw(|mut f| {
+            let view_height = f.size().height.saturating_sub(headers_len + 4);
 
             // build layout
             let rects = Layout::default()
@@ -399,12 +1659,16 @@ fn view_selected(
                 .constraints(
                     [
                         Constraint::Length(headers.len() as u16 + 1),
-                        Constraint::Max(std::cmp::min(view_height - atts.len() as u16, body_len)),
+                        Constraint::Max(std::cmp::min(
+                            view_height.saturating_sub(atts.len() as u16),
+                            body_len,
+                        )),
                         Constraint::Length(atts.len() as u16),
                     ]
                     .as_ref(),
                 )
                 .split(f.size());
+            viewport.resize(body_len, rects[1].height);
 
             // render headers
             f.render_widget(
@@ -420,12 +1684,12 @@ fn view_selected(
                 Paragraph::new(body_text.iter())
                     .block(Block::default())
                     .alignment(Alignment::Left)
-                    .scroll(scroll)
-                    .wrap(true),
+                    .scroll(viewport.offset),
                 rects[1],
             );
 
             // render attachments
+            atts_top = rects[2].y + 1;
             let items: Vec<Text> = atts
                 .iter()
                 .map(|att| match att {
@@ -451,43 +1715,742 @@ fn view_selected(
                     .style(app.styles.attachment),
                 rects[2],
             );
+
+            // render prompt or status on the last line
+            let size = f.size();
+            let line = Rect::new(size.x, size.bottom().saturating_sub(1), size.width, 1);
+            match &prompt {
+                Some((prompt, _)) => f.render_widget(
+                    Paragraph::new([Text::styled(prompt.text(), app.styles.selected)].iter()),
+                    line,
+                ),
+                None => render_status(&mut f, app, &AppState::View, &viewport.indicator(), line),
+            }
         })?;
 
-        match io::stdin().keys().next().unwrap() {
-            Ok(Key::Char('q')) | Ok(Key::Char('i')) => break,
-            Ok(Key::Char('j')) | Ok(Key::Down) => {
-                if scroll < scroll_max {
-                    scroll += 1;
-                } else {
-                    match selected_att {
-                        Some(selected) if selected < atts.len() - 1 => {
-                            selected_att = Some(selected + 1);
+        match &prompt {
+            Some((prompt, _)) => {
+                let y = terminal.size()?.bottom().saturating_sub(1);
+                terminal.show_cursor()?;
+                terminal.set_cursor(prompt.cursor(), y)?;
+            }
+            None => terminal.hide_cursor()?,
+        }
+
+        if let Some((mut p, mut action)) = prompt.take() {
+            let input = app.input.event()?;
+            // confirmation is answered with a single key
+            if let ViewPrompt::Reply(partstat) = action {
+                if let (Event::Key(Key::Char('y')), Some(event)) = (&input, &event) {
+                    match reply_invitation(app, event, partstat) {
+                        Ok(to) => app
+                            .status
+                            .info(format!("Sent {} reply to {}", partstat, to)),
+                        Err(e) => app.status.error(format!("Sending reply failed: {}", e)),
+                    }
+                }
+                continue;
+            }
+            if let (ViewPrompt::Pipe(mode), Event::Key(key)) = (&mut action, &input) {
+                if !p.is_pasting() && pipe::handle_key(key, &mut p, mode, &mut app.pipe_history) {
+                    prompt = Some((p, action));
+                    continue;
+                }
+            }
+            match (p.handle_event(input, &mut app.kill_ring), action) {
+                (PromptEvent::Submit(dir), ViewPrompt::Save(target)) => {
+                    app.status
+                        .report(save_attachments(&msg.id, &atts, target, &dir));
+                }
+                (PromptEvent::Submit(pattern), ViewPrompt::Search(back)) => {
+                    backward = back;
+                    if !pattern.is_empty() {
+                        patterns = vec![pattern];
+                    }
+                    let matches: Vec<u16> = rows
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, (line, _, _))| !search::find(line, &patterns).is_empty())
+                        .map(|(i, _)| i as u16)
+                        .collect();
+                    match next_match(&matches, viewport.offset, backward, true) {
+                        Some(line) => {
+                            viewport.scroll_to(line);
+                            match_line = Some(line);
                         }
-                        None if !atts.is_empty() => selected_att = Some(0),
-                        _ => {}
+                        None => app
+                            .status
+                            .error(format!("Pattern not found: {}", patterns.join(" "))),
+                    }
+                }
+                (PromptEvent::Submit(command), ViewPrompt::Pipe(mode)) => {
+                    app.pipe_history.add(&command);
+                    let ids = vec![msg.id.to_string()];
+                    if let Some(error) = pipe_messages(app, terminal, &ids, mode, &command)? {
+                        app.status.error(error);
+                    }
+                }
+                (PromptEvent::Submit(command), ViewPrompt::Command) => {
+                    if let Some(error) = run_command(app, terminal, &command)? {
+                        app.status.error(error);
                     }
                 }
+                // replies are confirmed before editing
+                (PromptEvent::Cancel, _) | (_, ViewPrompt::Reply(_)) => {}
+                (PromptEvent::Edit, action) => prompt = Some((p, action)),
             }
-            Ok(Key::Char('k')) | Ok(Key::Up) => {
-                if let Some(selected) = selected_att {
-                    match selected > 0 {
-                        true => selected_att = Some(selected - 1),
-                        _ => selected_att = None,
+            continue;
+        }
+
+        // redraw when the status message expires
+        if !app.input.wait(app.status.expires_in()) {
+            continue;
+        }
+        let key = match app.input.event() {
+            Ok(Event::Key(key)) => Ok(key),
+            Ok(Event::Mouse(MouseEvent::Press(button, _, y))) => {
+                match button {
+                    MouseButton::WheelDown => viewport.scroll_by(mouse::WHEEL_LINES as i32),
+                    MouseButton::WheelUp => viewport.scroll_by(-(mouse::WHEEL_LINES as i32)),
+                    // mouse rows are 1-based
+                    MouseButton::Left
+                        if y > atts_top && ((y - 1 - atts_top) as usize) < atts.len() =>
+                    {
+                        let selected = (y - 1 - atts_top) as usize;
+                        selected_att = Some(selected);
+                        if let Err(e) = show_attachment(app, terminal, &msg.id, &atts[selected]) {
+                            app.status
+                                .error(format!("Failed to show attachment: {}", e));
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            Ok(_) => continue,
+            Err(e) => Err(e),
+        };
+        if let Ok(key) = &key {
+            if viewport.page_key(key).is_some() {
+                continue;
+            }
+        }
+        match key {
+            Ok(Key::Char('q')) | Ok(Key::Char('i')) => break,
+            Ok(Key::Char('h')) => {
+                mode = match mode {
+                    ViewMode::Normal => ViewMode::Headers,
+                    ViewMode::Headers => ViewMode::Raw,
+                    ViewMode::Raw => ViewMode::Normal,
+                };
+                if mode == ViewMode::Raw && raw.is_none() {
+                    match notmuch::raw_message(&msg.id) {
+                        Ok(data) => raw = Some(String::from_utf8_lossy(&data).to_string()),
+                        Err(e) => {
+                            app.status
+                                .error(format!("Failed to get raw message: {}", e));
+                            mode = ViewMode::Normal;
+                        }
                     }
-                } else if scroll > 0 {
-                    scroll -= 1;
                 }
+                viewport.scroll_to(0);
+                match_line = None;
             }
-            Ok(Key::Char('g')) => match io::stdin().keys().next().unwrap() {
-                Ok(Key::Char('g')) => scroll = 0,
+            Ok(Key::Char('j')) | Ok(Key::Down) => viewport.scroll_by(1),
+            Ok(Key::Char('k')) | Ok(Key::Up) => viewport.scroll_by(-1),
+            Ok(Key::Char('g')) => match app.input.key() {
+                Ok(Key::Char('g')) => viewport.scroll_to(0),
                 _ => {}
             },
-            Ok(Key::Char('G')) => scroll = scroll_max,
+            Ok(Key::Char('G')) => viewport.scroll_to(viewport.max()),
+            Ok(Key::Char('z')) => {
+                if let Ok(Key::Char(ch)) = app.input.key() {
+                    viewport.align(match_line.unwrap_or(viewport.offset), ch);
+                }
+            }
+            // attachments are selected apart from scrolling
+            Ok(Key::Char('\t')) => {
+                selected_att = match selected_att {
+                    Some(selected) if selected + 1 < atts.len() => Some(selected + 1),
+                    Some(_) => None,
+                    None => match atts.is_empty() {
+                        true => None,
+                        _ => Some(0),
+                    },
+                }
+            }
+            Ok(Key::BackTab) => {
+                selected_att = match selected_att {
+                    Some(0) => None,
+                    Some(selected) => Some(selected - 1),
+                    None => atts.len().checked_sub(1),
+                }
+            }
+            Ok(Key::Char('u')) => {
+                let links = links::extract(&body, html_part);
+                if !links.is_empty() {
+                    pick_link(app, terminal, &links)?;
+                }
+            }
             Ok(Key::Char('\n')) => {
                 if let Some(selected) = selected_att {
-                    show_attachment(&msg.id, &atts[selected as usize])?;
+                    if let Err(e) = show_attachment(app, terminal, &msg.id, &atts[selected]) {
+                        app.status
+                            .error(format!("Failed to show attachment: {}", e));
+                    }
                 }
             }
+            Ok(Key::Char('s')) => match selected_att {
+                Some(_) => {
+                    let dir = attachment::default_dir(app.config.attachment_dir.as_deref());
+                    prompt = Some((
+                        Prompt::new("Save to: ", &dir),
+                        ViewPrompt::Save(selected_att),
+                    ));
+                }
+                None => app.status.error("No attachment selected"),
+            },
+            Ok(Key::Char('S')) => {
+                let dir = attachment::default_dir(app.config.attachment_dir.as_deref());
+                prompt = Some((Prompt::new("Save all to: ", &dir), ViewPrompt::Save(None)));
+            }
+            Ok(Key::Char('|')) => {
+                let mode = pipe::Mode::Raw;
+                prompt = Some((Prompt::new(&mode.label(), ""), ViewPrompt::Pipe(mode)));
+            }
+            Ok(Key::Char('f')) => {
+                match rows
+                    .iter()
+                    .skip(viewport.offset as usize)
+                    .find_map(|(_, _, block)| *block)
+                {
+                    Some(n) => folded[n] = !folded[n],
+                    None => app.status.error("No quote or signature below"),
+                }
+            }
+            Ok(Key::Char('F')) => {
+                folded = match folded.iter().any(|f| *f) {
+                    true => vec![false; blocks.len()],
+                    _ => quote::default_folded(&source, &blocks),
+                };
+            }
+            Ok(Key::Char('/')) => prompt = Some((Prompt::new("/", ""), ViewPrompt::Search(false))),
+            Ok(Key::Char('?')) => prompt = Some((Prompt::new("?", ""), ViewPrompt::Search(true))),
+            Ok(Key::Char(':')) => prompt = Some((Prompt::new(":", ""), ViewPrompt::Command)),
+            Ok(Key::Char(ch @ 'n')) | Ok(Key::Char(ch @ 'N')) => {
+                let matches: Vec<u16> = found
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, ranges)| !ranges.is_empty())
+                    .map(|(i, _)| i as u16)
+                    .collect();
+                match next_match(&matches, viewport.offset, backward == (ch == 'n'), false) {
+                    Some(line) => {
+                        viewport.scroll_to(line);
+                        match_line = Some(line);
+                    }
+                    None if patterns.is_empty() => app.status.error("No search pattern"),
+                    None => app
+                        .status
+                        .error(format!("Pattern not found: {}", patterns.join(" "))),
+                }
+            }
+            Ok(Key::Char(ch @ 'a')) | Ok(Key::Char(ch @ 't')) | Ok(Key::Char(ch @ 'd')) => {
+                match &event {
+                    Some(event) if event.is_request() => {
+                        let partstat = match ch {
+                            'a' => "ACCEPTED",
+                            't' => "TENTATIVE",
+                            _ => "DECLINED",
+                        };
+                        match &event.organizer {
+                            Some(organizer) => {
+                                let label = format!(
+                                    "Send {} reply to {}? (y/n) ",
+                                    partstat,
+                                    organizer.display()
+                                );
+                                prompt =
+                                    Some((Prompt::new(&label, ""), ViewPrompt::Reply(partstat)));
+                            }
+                            None => app.status.error("Invitation has no organizer"),
+                        }
+                    }
+                    Some(_) => app.status.error("Not an invitation request"),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// wrap at whitespace when possible
+fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    // quoted lines are continued with the same quote prefix
+    let prefix = match line.starts_with('>') {
+        true => &line[..line.len() - line.trim_start_matches(|c| c == '>' || c == ' ').len()],
+        _ => "",
+    };
+    let prefix = match prefix.len() < width / 2 {
+        true => prefix,
+        _ => "",
+    };
+
+    width::wrap(line, width, prefix)
+}
+
+// body text of a message, html only messages rendered to width
+fn message_text(
+    app: &App,
+    msg: &notmuch::Message,
+    width: u16,
+) -> Result<(String, Vec<notmuch::Attachment>), failure::Error> {
+    let (body, atts) = notmuch::body_attachments(&msg.id, &msg.body)?;
+    let html = atts.iter().find_map(|att| match att {
+        notmuch::Attachment::Html(html, _) if body.is_empty() => Some(html.as_str()),
+        _ => None,
+    });
+
+    match html {
+        Some(html) => {
+            let body = notmuch::html_to_text(html, width, app.config.html_renderer.as_deref())?;
+            Ok((body, atts))
+        }
+        None => Ok((body, atts)),
+    }
+}
+
+// whole thread of the selected message, one collapsible section per message
+fn view_thread(
+    app: &mut App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+) -> Result<(), failure::Error> {
+    debug!("view_thread");
+
+    app.state = AppState::Index;
+
+    let selected = app.messages.get_selected()?;
+    // messages are in thread order unless the index is flat
+    let mut indexes: Vec<usize> = app
+        .messages
+        .list
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.thread == selected.thread)
+        .map(|(i, _)| i)
+        .collect();
+    if !app.threaded {
+        indexes.sort_by_key(|i| app.messages.list[*i].timestamp);
+    }
+    let mut current = indexes
+        .iter()
+        .position(|i| *i == app.messages.selected as usize)
+        .unwrap_or(0);
+    let title = format_subject(selected.headers.get("Subject"));
+
+    // unread messages start expanded
+    let mut expanded: Vec<bool> = indexes
+        .iter()
+        .map(|i| app.messages.list[*i].tags.iter().any(|t| t == "unread"))
+        .collect();
+    let mut bodies: Vec<Option<(u16, Vec<String>)>> = vec![None; indexes.len()];
+
+    let mut viewport = scroll::Viewport::default();
+    let mut follow = true;
+
+    loop {
+        let size = terminal.size()?;
+        let width = size.width.saturating_sub(4);
+        let height = size.height.saturating_sub(4);
+
+        let mut lines: Vec<Text> = vec![];
+        let mut line_count: u16 = 0;
+        let mut starts: Vec<u16> = vec![];
+        for (n, i) in indexes.iter().enumerate() {
+            let msg = &app.messages.list[*i];
+            starts.push(line_count);
+
+            let header = format!(
+                "{} {}{}  {}  ",
+                if expanded[n] { "\u{25be}" } else { "\u{25b8}" },
+                match app.threaded {
+                    true => "  ".repeat(msg.depth),
+                    _ => "".to_string(),
+                },
+                msg.headers.get("From").map(|s| s.as_str()).unwrap_or("n/a"),
+                msg.date_relative,
+            );
+            let style = match n == current {
+                true => app.styles.selected,
+                _ => app.styles.header,
+            };
+            lines.push(Text::styled(header, style));
+            for (text, style) in tag_spans(app, &msg.tags, "thread", style) {
+                lines.push(Text::styled(text, style));
+            }
+            lines.push(Text::raw("\n"));
+            line_count += 1;
+            if !expanded[n] {
+                continue;
+            }
+
+            let rendered = match &bodies[n] {
+                Some((w, _)) => *w == width,
+                None => false,
+            };
+            if !rendered {
+                let (body, atts) = message_text(app, msg, width)?;
+                let mut text: Vec<String> = body
+                    .lines()
+                    .flat_map(|line| wrap_line(line, width as usize))
+                    .collect();
+                text.extend(atts.iter().map(|att| match att {
+                    notmuch::Attachment::File(_, _, _, name) => name.trim_end().to_string(),
+                    notmuch::Attachment::Html(_, name) => name.trim_end().to_string(),
+                }));
+                bodies[n] = Some((width, text));
+            }
+            if let Some((_, text)) = &bodies[n] {
+                for line in text {
+                    lines.push(Text::raw(format!("{}\n", line)));
+                }
+                line_count += text.len() as u16;
+            }
+            lines.push(Text::raw("\n"));
+            line_count += 1;
+        }
+
+        viewport.resize(line_count, height);
+        if follow {
+            viewport.scroll_to(starts[current]);
+            follow = false;
+        }
+
+        terminal.draw(|mut f| {
+            let area = Rect::new(
+                1,
+                1,
+                size.width.saturating_sub(2),
+                size.height.saturating_sub(2),
+            );
+            f.render_widget(
+                Paragraph::new(lines.iter())
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title(&title)
+                            .title_style(app.styles.subject),
+                    )
+                    .alignment(Alignment::Left)
+                    .scroll(viewport.offset),
+                area,
+            );
+            let line = Rect::new(0, size.bottom().saturating_sub(1), size.width, 1);
+            render_status(&mut f, app, &AppState::Thread, &viewport.indicator(), line);
+        })?;
+
+        // redraw when the status message expires
+        if !app.input.wait(app.status.expires_in()) {
+            continue;
+        }
+        let key = app.input.key();
+        if let Ok(key) = &key {
+            if viewport.page_key(key).is_some() {
+                current = starts
+                    .iter()
+                    .rposition(|s| *s <= viewport.offset)
+                    .unwrap_or(0);
+                continue;
+            }
+        }
+        match key {
+            Ok(Key::Char('q')) | Ok(Key::Char('i')) => break,
+            Ok(Key::Char('j')) | Ok(Key::Down) => viewport.scroll_by(1),
+            Ok(Key::Char('k')) | Ok(Key::Up) => viewport.scroll_by(-1),
+            Ok(Key::Char('z')) => {
+                if let Ok(Key::Char(ch)) = app.input.key() {
+                    viewport.align(starts[current], ch);
+                }
+                continue;
+            }
+            Ok(Key::Char('g')) => match app.input.key() {
+                Ok(Key::Char('g')) => {
+                    current = 0;
+                    follow = true;
+                }
+                _ => {}
+            },
+            Ok(Key::Char('G')) => {
+                current = indexes.len() - 1;
+                follow = true;
+            }
+            Ok(Key::Char('J')) => {
+                if current + 1 < indexes.len() {
+                    current += 1;
+                }
+                follow = true;
+            }
+            Ok(Key::Char('K')) => {
+                current = current.saturating_sub(1);
+                follow = true;
+            }
+            Ok(Key::Char('\n')) | Ok(Key::Char('o')) => {
+                expanded[current] = !expanded[current];
+                follow = true;
+            }
+            Ok(Key::Char('O')) => {
+                let expand = expanded.iter().any(|e| !e);
+                expanded.iter_mut().for_each(|e| *e = expand);
+                follow = true;
+            }
+            Ok(Key::Char('v')) => {
+                app.messages.selected = indexes[current] as u16;
+                app.state = AppState::View;
+                break;
+            }
+            _ => {}
+        }
+
+        // message under the top of the view follows line scrolling
+        if !follow {
+            current = starts
+                .iter()
+                .rposition(|s| *s <= viewport.offset)
+                .unwrap_or(0);
+        }
+    }
+
+    Ok(())
+}
+
+// first text/calendar part with an event
+fn invitation(id: &str, atts: &[notmuch::Attachment]) -> Option<ical::Event> {
+    atts.iter().find_map(|att| match att {
+        notmuch::Attachment::File(part, _, mime, _) if mime == "text/calendar" => {
+            let data = notmuch::raw_part(id, *part).ok()?;
+            let calendar = ical::parse(&notmuch::decode(&data, None)?)?;
+            ical::Event::from(&calendar)
+        }
+        _ => None,
+    })
+}
+
+fn format_invitation<'a>(app: &App, event: &ical::Event) -> Vec<Text<'a>> {
+    debug!("format_invitation");
+
+    let when = match (&event.start, &event.end) {
+        (Some(start), Some(end)) => format!("{} - {}", start, end),
+        (Some(start), None) => start.to_string(),
+        _ => "".to_string(),
+    };
+    let mut lines = vec![
+        ("Invitation", event.summary.to_string()),
+        (
+            "Organizer",
+            event
+                .organizer
+                .as_ref()
+                .map(|o| o.display())
+                .unwrap_or_default(),
+        ),
+        ("When", when),
+    ];
+    if let Some(location) = &event.location {
+        lines.push(("Location", location.replace('\n', ", ")));
+    }
+    for attendee in &event.attendees {
+        lines.push((
+            "Attendee",
+            format!(
+                "{} ({})",
+                attendee.display(),
+                attendee.status.to_lowercase()
+            ),
+        ));
+    }
+    if event.is_request() {
+        lines.push(("Reply", "a accept, t tentative, d decline".to_string()));
+    }
+
+    let mut result = vec![Text::raw("\n")];
+    for (name, value) in lines {
+        result.push(Text::styled(
+            format!("{}: {}\n", name, value),
+            app.styles.attachment,
+        ));
+    }
+
+    result
+}
+
+// send iTIP REPLY to organizer, returns organizer address
+fn reply_invitation(
+    app: &App,
+    event: &ical::Event,
+    partstat: &str,
+) -> Result<String, failure::Error> {
+    debug!("reply_invitation: {}", partstat);
+
+    let organizer = event
+        .organizer
+        .as_ref()
+        .ok_or(failure::format_err!("Invitation has no organizer"))?;
+    // reply as the identity that was invited
+    let identity = app
+        .config
+        .identities
+        .iter()
+        .find(|id| {
+            event
+                .attendees
+                .iter()
+                .any(|a| a.address.eq_ignore_ascii_case(&id.address))
+        })
+        .or_else(|| app.config.identity(""))
+        .ok_or(failure::format_err!("No identity configured"))?;
+
+    let verb = match partstat {
+        "ACCEPTED" => "Accepted",
+        "TENTATIVE" => "Tentative",
+        _ => "Declined",
+    };
+    let mut draft = Draft::new(identity);
+    draft.to = organizer.address.to_string();
+    draft.subject = format!("{}: {}", verb, event.summary);
+    draft.body = format!(
+        "{} has {} the invitation: {}\n",
+        identity.name,
+        match partstat {
+            "ACCEPTED" => "accepted",
+            "TENTATIVE" => "tentatively accepted",
+            _ => "declined",
+        },
+        event.summary
+    );
+    draft.calendar = Some(event.reply(&identity.address, &identity.name, partstat));
+    // don't fail the reply for recipients without keys
+    if draft.encrypt && !compose::missing_keys(&draft, &app.autocrypt).is_empty() {
+        draft.encrypt = false;
+    }
+
+    let data = compose::build(&draft, &app.config, &app.autocrypt, app.tmp_dir.path())?;
+    compose::send(&data, &app.config)?;
+
+    Ok(organizer.address.to_string())
+}
+
+fn save_attachments(
+    id: &str,
+    atts: &[notmuch::Attachment],
+    selected: Option<usize>,
+    dir: &str,
+) -> Result<String, failure::Error> {
+    debug!("save_attachments: {:?} {}", selected, dir);
+
+    let dir = attachment::expand_dir(dir);
+    let targets: Vec<&notmuch::Attachment> = match selected {
+        Some(selected) => atts.get(selected).into_iter().collect(),
+        None => atts
+            .iter()
+            .filter(|att| matches!(att, notmuch::Attachment::File(..)))
+            .collect(),
+    };
+
+    let mut saved = vec![];
+    for att in targets {
+        match attachment::save(id, att, &dir) {
+            Ok(path) => saved.push(path),
+            Err(e) => failure::bail!("Saving failed: {}", e),
+        }
+    }
+
+    match saved.as_slice() {
+        [path] => Ok(format!("Saved {}", path.display())),
+        _ => Ok(format!(
+            "Saved {} attachments to {}",
+            saved.len(),
+            dir.display()
+        )),
+    }
+}
+
+fn pick_link(
+    app: &App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    links: &[links::Link],
+) -> Result<(), failure::Error> {
+    debug!("pick_link, count: {}", links.len());
+
+    let mut selected = 0;
+    let mut viewport = scroll::Viewport::default();
+    let mut message = String::new();
+
+    loop {
+        terminal.draw(|mut f| {
+            let rects = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(1)
+                .constraints([Constraint::Percentage(100), Constraint::Length(1)].as_ref())
+                .split(f.size());
+
+            viewport.resize(links.len() as u16, rects[0].height.saturating_sub(2));
+            viewport.show(selected as u16);
+
+            let mut items = vec![];
+            for (i, link) in links.iter().enumerate() {
+                let style = match i == selected {
+                    true => app.styles.selected,
+                    _ => app.styles.normal,
+                };
+                items.push(Text::styled(format!("[{}] ", i + 1), style));
+                if let Some(domain) = &link.mismatch {
+                    items.push(Text::styled(
+                        format!("(text shows {}) ", domain),
+                        app.styles.error,
+                    ));
+                }
+                if !link.text.is_empty() {
+                    items.push(Text::styled(format!("{}: ", link.text), app.styles.header));
+                }
+                items.push(Text::styled(format!("{}\n", link.url), style));
+            }
+
+            f.render_widget(
+                Paragraph::new(items.iter())
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title(" Links  Enter:open  y:copy  q:back "),
+                    )
+                    .alignment(Alignment::Left)
+                    .scroll(viewport.offset),
+                rects[0],
+            );
+
+            let status = [Text::raw(message.as_str())];
+            f.render_widget(Paragraph::new(status.iter()), rects[1]);
+        })?;
+
+        let link = &links[selected];
+        match app.input.key() {
+            Ok(Key::Char('j')) | Ok(Key::Down) if selected + 1 < links.len() => selected += 1,
+            Ok(Key::Char('k')) | Ok(Key::Up) if selected > 0 => selected -= 1,
+            Ok(Key::Char('\n')) => {
+                message = match links::open(&link.url, &app.config.opener) {
+                    Ok(_) => format!("Opened {}", link.url),
+                    Err(e) => format!("Failed to open {}: {}", link.url, e),
+                };
+            }
+            Ok(Key::Char('y')) => {
+                message = match links::copy(&link.url, &app.config.clipboard) {
+                    Ok(_) => format!("Copied {}", link.url),
+                    Err(e) => format!("Failed to copy {}: {}", link.url, e),
+                };
+            }
+            Ok(Key::Char('q')) | Ok(Key::Esc) => break,
             _ => {}
         }
     }
@@ -501,6 +2464,7 @@ fn write_file(fname: &std::path::PathBuf, data: &[u8]) -> Result<(), failure::Er
     match fs::OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .mode(0o600)
         .open(fname)
     {
@@ -510,31 +2474,173 @@ fn write_file(fname: &std::path::PathBuf, data: &[u8]) -> Result<(), failure::Er
     Ok(())
 }
 
-fn show_attachment(id: &str, attachment: &notmuch::Attachment) -> Result<(), failure::Error> {
-    debug!("show_attachment");
+// run command with the terminal in normal mode
+fn run_interactive(
+    app: &App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    command: &mut Command,
+) -> Result<std::process::ExitStatus, failure::Error> {
+    debug!("run_interactive: {:?}", command);
 
-    let mut tmp_file = std::env::temp_dir();
+    terminal.show_cursor()?;
+    write!(
+        terminal.backend_mut(),
+        "{}{}",
+        termion::clear::All,
+        Goto(1, 1)
+    )?;
+    write!(
+        terminal.backend_mut(),
+        "{}{}",
+        prompt::PASTE_OFF,
+        mouse::OFF
+    )?;
+    terminal.backend_mut().flush()?;
+    let _ = Command::new("stty").arg("sane").status();
+
+    let status = command.status();
+
+    let _ = Command::new("stty").args(&["raw", "-echo"]).status();
+    write!(terminal.backend_mut(), "{}", prompt::PASTE_ON)?;
+    if app.config.mouse {
+        write!(terminal.backend_mut(), "{}", mouse::ON)?;
+    }
+    terminal.hide_cursor()?;
+    terminal.clear()?;
 
-    match attachment {
-        notmuch::Attachment::File(part, fname, _mime, _name) => {
-            tmp_file.push(fname);
+    Ok(status?)
+}
 
-            let child = Command::new("notmuch")
-                .args(&["show", "--format=raw"])
-                .arg(format!("--part={}", part))
-                .arg(format!("id:{}", id))
-                .output()?;
+fn show_pager(
+    app: &App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    title: &str,
+    content: &str,
+) -> Result<(), failure::Error> {
+    debug!("show_pager: {}", title);
+
+    let lines: Vec<&str> = content.lines().collect();
+    let text: Vec<Text> = match diff_styles(app, &lines) {
+        Some((stat, styles)) => stat
+            .iter()
+            .map(|line| Text::styled(format!("{}\n", line), app.styles.header))
+            .chain(std::iter::once(Text::raw("\n")))
+            .chain(lines.iter().zip(styles.iter()).map(|(line, style)| {
+                Text::styled(format!("{}\n", line), style.unwrap_or(app.styles.normal))
+            }))
+            .collect(),
+        None => lines
+            .iter()
+            .map(|line| Text::raw(format!("{}\n", line)))
+            .collect(),
+    };
+    let content_len = text.len() as u16;
+    let mut viewport = scroll::Viewport::default();
 
-            write_file(&tmp_file, &child.stdout)?;
-        }
-        notmuch::Attachment::Html(s, _name) => {
-            tmp_file.push(format!("{}.html", id));
+    loop {
+        terminal.draw(|mut f| {
+            // lines below the title border
+            viewport.resize(content_len, f.size().height.saturating_sub(1));
+            let title = format!(" {} ({}) ", title, viewport.indicator());
+
+            f.render_widget(
+                Paragraph::new(text.iter())
+                    .block(
+                        Block::default()
+                            .borders(Borders::TOP)
+                            .title(&title)
+                            .title_style(app.styles.header),
+                    )
+                    .alignment(Alignment::Left)
+                    .scroll(viewport.offset),
+                f.size(),
+            );
+        })?;
 
-            write_file(&tmp_file, s.as_bytes())?;
+        let key = app.input.key();
+        if let Ok(key) = &key {
+            if viewport.page_key(key).is_some() {
+                continue;
+            }
         }
+        match key {
+            Ok(Key::Char('q')) | Ok(Key::Char('i')) | Ok(Key::Esc) => break,
+            Ok(Key::Char('j')) | Ok(Key::Down) => viewport.scroll_by(1),
+            Ok(Key::Char('k')) | Ok(Key::Up) => viewport.scroll_by(-1),
+            Ok(Key::Char('g')) => match app.input.key() {
+                Ok(Key::Char('g')) => viewport.scroll_to(0),
+                _ => {}
+            },
+            Ok(Key::Char('G')) => viewport.scroll_to(viewport.max()),
+            Ok(Key::Char('z')) => {
+                if let Ok(Key::Char(ch)) = app.input.key() {
+                    viewport.align(viewport.offset, ch);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// view attachment with mailcap entry for its type, fallback to opener
+fn show_attachment(
+    app: &App,
+    terminal: &mut Terminal<TermionBackend<RawTerminal<Stdout>>>,
+    id: &str,
+    attachment: &notmuch::Attachment,
+) -> Result<(), failure::Error> {
+    debug!("show_attachment");
+
+    let content_type = match attachment {
+        notmuch::Attachment::File(_, _, mime, _) => mime.as_str(),
+        notmuch::Attachment::Html(_, _) => "text/html",
+    };
+    let fname = attachment::filename(id, attachment);
+    // patches are shown highlighted instead of with mailcap
+    if is_patch(&fname, content_type) {
+        let content = attachment::content(id, attachment)?;
+        let title = format!("{} ({})", fname, content_type);
+        return show_pager(app, terminal, &title, &String::from_utf8_lossy(&content));
     }
 
-    let _child = Command::new("xdg-open").arg(tmp_file).status()?;
+    let entry = app.mailcap.lookup(content_type);
+
+    let fname = entry.map_or(fname.to_string(), |entry| entry.filename(&fname));
+    let tmp_file = app.tmp_dir.path().join(fname);
+    write_file(&tmp_file, &attachment::content(id, attachment)?)?;
+
+    match entry {
+        Some(entry) if entry.copiousoutput => {
+            let output = entry
+                .command(&tmp_file, content_type)?
+                .stderr(Stdio::null())
+                .output()?;
+            let title = format!(
+                "{} ({})",
+                attachment::filename(id, attachment),
+                content_type
+            );
+            show_pager(
+                app,
+                terminal,
+                &title,
+                &String::from_utf8_lossy(&output.stdout),
+            )?;
+        }
+        Some(entry) if entry.needsterminal => {
+            run_interactive(app, terminal, &mut entry.command(&tmp_file, content_type)?)?;
+        }
+        Some(entry) => {
+            entry
+                .command(&tmp_file, content_type)?
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()?;
+        }
+        None => links::open(&tmp_file.display().to_string(), &app.config.opener)?,
+    }
 
     Ok(())
 }
@@ -545,6 +2651,8 @@ fn get_terminal() -> Result<Terminal<TermionBackend<RawTerminal<Stdout>>>, failu
     let mut terminal = Terminal::new(backend)?;
     terminal.clear()?;
     terminal.hide_cursor()?;
+    // pasted text is told apart from typed keys in prompts
+    write!(terminal.backend_mut(), "{}", prompt::PASTE_ON)?;
 
     Ok(terminal)
 }
@@ -553,30 +2661,86 @@ fn main() -> Result<(), failure::Error> {
     env_logger::init();
     debug!("main");
 
-    let mut app = App::new();
+    let config = config::load()?;
+    let tmp_dir = tmpdir::TmpDir::new(config.keep_temp_files)?;
+    let mut app = App::new(config, autocrypt::Store::load()?, tmp_dir);
     let mut terminal = get_terminal()?;
+    if app.config.mouse {
+        write!(terminal.backend_mut(), "{}", mouse::ON)?;
+    }
 
     loop {
-        match app.state {
+        let result = match app.state {
             AppState::Refresh => {
                 debug!("AppState::Refresh");
-                refresh_index(&mut app)?;
+                show_progress(
+                    &mut terminal,
+                    &app.state,
+                    &format!("Searching {}", app.search_term),
+                )?;
+                let result = refresh_index(&mut app);
+                terminal.clear()?;
+                result
             }
+            // errors are shown on the index, so its own are fatal
             AppState::Index => {
                 show_index(&mut app, &mut terminal)?;
+                Ok(())
             }
-            AppState::View => {
-                view_selected(&mut app, &mut terminal)?;
-            }
-            AppState::Compose => {
-                compose(&mut app, &mut terminal)?;
-            }
+            AppState::View => view_selected(&mut app, &mut terminal),
+            AppState::Thread => view_thread(&mut app, &mut terminal),
+            AppState::Compose => compose(&mut app, &mut terminal),
             AppState::Exit => {
+                write!(
+                    terminal.backend_mut(),
+                    "{}{}",
+                    prompt::PASTE_OFF,
+                    mouse::OFF
+                )?;
                 break;
             }
-            _ => (),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("main: {}", e);
+            app.status.error(e.to_string());
+            app.state = AppState::Index;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_prefixes_are_removed() {
+        assert_eq!(base_subject("Re: RE:re:  Hello"), "hello");
+        assert_eq!(base_subject("Regarding"), "regarding");
+        assert_eq!(base_subject("re:"), "");
+        assert_eq!(base_subject(""), "");
+    }
+
+    #[test]
+    fn forward_and_list_prefixes_are_removed() {
+        assert_eq!(base_subject("Fwd: FW: Re: Hello"), "hello");
+        assert_eq!(base_subject("[nutt-dev] Re: [nutt-dev] Hello"), "hello");
+        assert_eq!(base_subject("Re[2]: Re(3): Hello"), "hello");
+        assert_eq!(base_subject("RE : Hello"), "hello");
+        assert_eq!(base_subject("Fwd:[list]"), "");
+        // colons in the subject itself
+        assert_eq!(base_subject("Meeting: today"), "meeting: today");
+        assert_eq!(base_subject("Fwd: Agenda: Re"), "agenda: re");
+        assert_eq!(base_subject("[unclosed Re: x"), "[unclosed re: x");
+    }
+
+    #[test]
+    fn non_ascii_subjects_are_kept() {
+        assert_eq!(base_subject("Äänestys"), "äänestys");
+        assert_eq!(base_subject("🎉 party"), "🎉 party");
+        assert_eq!(base_subject("Re: Ää"), "ää");
+        assert_eq!(base_subject("ä"), "ä");
+    }
+}
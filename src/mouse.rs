@@ -0,0 +1,55 @@
+use std::time::{Duration, Instant};
+
+// xterm mouse reporting, the same modes termion's MouseTerminal uses
+pub const ON: &str = "\x1b[?1000h\x1b[?1002h\x1b[?1015h\x1b[?1006h";
+pub const OFF: &str = "\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+
+// lines moved per wheel step
+pub const WHEEL_LINES: u16 = 3;
+
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+// tells double clicks apart from single ones
+#[derive(Default)]
+pub struct Clicks {
+    last: Option<(Instant, u16)>,
+}
+
+impl Clicks {
+    // true when this click repeats the previous one on the same row
+    pub fn click(&mut self, row: u16) -> bool {
+        let now = Instant::now();
+        let double = match self.last {
+            Some((time, last)) => last == row && now - time < DOUBLE_CLICK,
+            None => false,
+        };
+        self.last = match double {
+            true => None,
+            _ => Some((now, row)),
+        };
+        double
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_click_needs_same_row() {
+        let mut clicks = Clicks::default();
+        assert!(!clicks.click(3));
+        assert!(!clicks.click(4));
+        assert!(clicks.click(4));
+        // third click starts over
+        assert!(!clicks.click(4));
+    }
+
+    #[test]
+    fn slow_clicks_are_single() {
+        let mut clicks = Clicks {
+            last: Some((Instant::now() - DOUBLE_CLICK, 0)),
+        };
+        assert!(!clicks.click(0));
+    }
+}
@@ -1,3 +1,6 @@
+use crate::flowed;
+use crate::html;
+use encoding_rs::Encoding;
 use failure::bail;
 use log::*;
 use serde::{Deserialize, Serialize};
@@ -17,8 +20,6 @@ pub struct Body {
     id: usize,
     #[serde(rename = "content-type")]
     content_type: String,
-    #[serde(rename = "content-transfer-encoding")]
-    content_transfer_encoding: Option<String>,
     #[serde(rename = "content-charset")]
     content_charset: Option<String>,
     content: Option<Content>,
@@ -36,6 +37,12 @@ pub struct Message {
     pub headers: HashMap<String, String>,
     #[serde(skip)]
     pub depth: usize,
+    // index of the thread in search results
+    #[serde(skip)]
+    pub thread: usize,
+    // file size in bytes, read once when the index loads
+    #[serde(skip)]
+    pub size: Option<u64>,
     #[serde(skip)]
     pub replys: Vec<Message>,
     // #[serde(rename = "match", skip)]
@@ -52,29 +59,50 @@ pub enum Node {
     Children(Vec<Vec<Node>>),
 }
 
-fn html_to_text(html: &str) -> Result<String, failure::Error> {
-    debug!("html_to_text: {}", html);
+pub fn html_to_text(
+    html: &str,
+    width: u16,
+    renderer: Option<&str>,
+) -> Result<String, failure::Error> {
+    debug!("html_to_text: width: {}", width);
+
+    let command = match renderer {
+        Some(command) => command.replace("%w", &width.to_string()),
+        None => return Ok(html::render(html, width as usize)),
+    };
 
-    let mut child = Command::new("lynx")
+    // stderr is kept off the screen, failures fall back to built-in rendering
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .arg("-stdin")
-        .arg("-dump")
-        .arg("-width")
-        .arg("80")
-        .arg("-display_charset=UTF-8")
+        .stderr(Stdio::piped())
         .spawn()?;
 
-    let stdin = child
+    // written from a thread so large output can't block the input
+    let mut stdin = child
         .stdin
-        .as_mut()
-        .ok_or(failure::format_err!("Failed to run lynx"))?;
-    stdin.write_all(html.as_bytes())?;
+        .take()
+        .ok_or(failure::format_err!("Failed to run '{}'", command))?;
+    let data = html.as_bytes().to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&data);
+    });
 
     let output = child.wait_with_output()?;
-    let result = std::str::from_utf8(&output.stdout)?.to_string();
+    let _ = writer.join();
+    if !output.status.success() {
+        warn!(
+            "html_to_text: '{}' failed: {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Ok(html::render(html, width as usize));
+    }
 
-    Ok(result)
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
 }
 
 pub enum Attachment {
@@ -84,29 +112,214 @@ pub enum Attachment {
     File(usize, String, String, String),
 }
 
-pub fn body_attachments(bodys: &Vec<Body>) -> Result<(String, Vec<Attachment>), failure::Error> {
+// decode part content with charset, None if not possible
+pub fn decode(data: &[u8], charset: Option<&str>) -> Option<String> {
+    let encoding = match charset {
+        Some(charset) => Encoding::for_label(charset.trim().as_bytes())?,
+        // undeclared charset, try utf-8 first
+        None => match std::str::from_utf8(data) {
+            Ok(s) => return Some(s.to_string()),
+            Err(_) => encoding_rs::WINDOWS_1252,
+        },
+    };
+
+    encoding
+        .decode_without_bom_handling_and_without_replacement(data)
+        .map(|s| s.into_owned())
+}
+
+pub fn raw_part(id: &str, part: usize) -> Result<Vec<u8>, failure::Error> {
+    debug!("raw_part: {} {}", id, part);
+
+    let output = Command::new("notmuch")
+        .args(&["show", "--format=raw"])
+        .arg(format!("--part={}", part))
+        .arg(format!("id:{}", id))
+        .output()?;
+    if !output.status.success() {
+        bail!("notmuch show failed for part {} of {}", part, id);
+    }
+
+    Ok(output.stdout)
+}
+
+pub fn raw_message(id: &str) -> Result<Vec<u8>, failure::Error> {
+    debug!("raw_message: {}", id);
+
+    let output = Command::new("notmuch")
+        .args(&["show", "--format=raw"])
+        .arg(format!("id:{}", id))
+        .output()?;
+    if !output.status.success() {
+        bail!("notmuch show failed for {}", id);
+    }
+
+    Ok(output.stdout)
+}
+
+// query matching any of the message ids
+pub fn id_query(ids: &[String]) -> String {
+    ids.iter()
+        .map(|id| format!("id:\"{}\"", id.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" or ")
+}
+
+// messages as mbox in one invocation
+pub fn mbox(ids: &[String]) -> Result<Vec<u8>, failure::Error> {
+    debug!("mbox: {}", ids.len());
+
+    let output = Command::new("notmuch")
+        .args(&["show", "--format=mbox", "--entire-thread=false"])
+        .arg(id_query(ids))
+        .output()?;
+    if !output.status.success() {
+        bail!("notmuch show failed");
+    }
+
+    Ok(output.stdout)
+}
+
+pub fn search_ids(query: &str) -> Result<Vec<String>, failure::Error> {
+    debug!("search_ids: {}", query);
+
+    let output = Command::new("notmuch")
+        .args(&["search", "--output=messages", "--format=json"])
+        .arg(query)
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    let ids: Vec<String> = serde_json::from_slice(&output.stdout)?;
+
+    Ok(ids)
+}
+
+// apply changes like '+todo -inbox' to all messages at once
+pub fn tag(ids: &[String], changes: &[String]) -> Result<(), failure::Error> {
+    debug!("tag: {} {:?}", ids.len(), changes);
+
+    let output = Command::new("notmuch")
+        .arg("tag")
+        .args(changes)
+        .arg("--")
+        .arg(id_query(ids))
+        .output()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(())
+}
+
+// text of part that notmuch could not decode
+fn decode_part(id: &str, b: &Body) -> String {
+    debug!(
+        "decode_part: {} {} {:?}",
+        b.id, &b.content_type, &b.content_charset
+    );
+
+    // notmuch undoes the transfer encoding of single parts
+    let decoded = raw_part(id, b.id)
+        .ok()
+        .and_then(|data| decode(&data, b.content_charset.as_deref()));
+
+    match decoded {
+        Some(text) => text,
+        None => format!(
+            "[nutt: unable to decode part {} ({}; charset={})]\n",
+            b.id,
+            b.content_type,
+            b.content_charset.as_deref().unwrap_or("unknown")
+        ),
+    }
+}
+
+pub fn body_attachments(
+    id: &str,
+    bodys: &Vec<Body>,
+) -> Result<(String, Vec<Attachment>), failure::Error> {
     debug!("body_attachments");
 
+    // notmuch json has no format parameter, raw is read only when it can matter
+    let flowed = match may_be_flowed(bodys) {
+        false => HashMap::new(),
+        true => match raw_message(id) {
+            Ok(raw) => flowed::parts(&String::from_utf8_lossy(&raw)),
+            Err(e) => {
+                warn!("body_attachments: {}", e);
+                HashMap::new()
+            }
+        },
+    };
+
+    collect_parts(id, bodys, &flowed)
+}
+
+// text/plain part that flowed decoding would change, soft breaks
+// and space stuffing both need a space at the line end or start
+fn may_be_flowed(bodys: &Vec<Body>) -> bool {
+    bodys.iter().any(|b| match &b.content {
+        Some(Content::Array(bs)) => may_be_flowed(bs),
+        _ if b.content_type != "text/plain" || b.filename.is_some() => false,
+        Some(Content::Str(s)) => s
+            .lines()
+            .any(|line| line.trim_end_matches('\r').ends_with(' ') || line.starts_with(' ')),
+        // content left out by notmuch, can't tell without raw
+        None => true,
+    })
+}
+
+// body text and attachments, 'flowed' has DelSp of format=flowed parts
+fn collect_parts(
+    id: &str,
+    bodys: &Vec<Body>,
+    flowed: &HashMap<usize, bool>,
+) -> Result<(String, Vec<Attachment>), failure::Error> {
     let mut body = String::from("");
     let mut body_html = String::from("");
 
     let mut attachments: Vec<Attachment> = vec![];
 
     for b in bodys {
-        match &b.content {
-            Some(Content::Str(s)) => match b.content_type.as_str() {
-                "text/html" => body_html.push_str(s),
-                _ => body.push_str(s),
-            },
+        let text = match &b.content {
+            // replacement chars mean notmuch guessed the charset wrong
+            Some(Content::Str(s)) if s.contains('\u{fffd}') && b.content_charset.is_some() => {
+                Some(decode_part(id, b))
+            }
+            Some(Content::Str(s)) => Some(s.to_string()),
             Some(Content::Array(bs)) => {
-                let (b, atts) = body_attachments(bs)?;
+                let (b, atts) = collect_parts(id, bs, flowed)?;
                 body.push_str(&b);
                 attachments.extend(atts);
+                None
+            }
+            None if b.content_type.starts_with("text/") && b.filename.is_none() => {
+                Some(decode_part(id, b))
+            }
+            _ => None,
+        };
+
+        let text = match (text, flowed.get(&b.id)) {
+            (Some(text), Some(delsp)) => Some(flowed::decode(&text, *delsp)),
+            (text, _) => text,
+        };
+
+        if let Some(text) = text {
+            match b.content_type.as_str() {
+                "text/html" => body_html.push_str(&text),
+                // invitations are shown parsed in the view
+                "text/calendar" => {}
+                _ => body.push_str(&text),
             }
-            _ => {}
         }
 
-        if let Some(filename) = &b.filename {
+        let filename = match (&b.filename, b.content_type.as_str()) {
+            (Some(filename), _) => Some(filename.to_string()),
+            (None, "text/calendar") => Some("invite.ics".to_string()),
+            _ => None,
+        };
+        if let Some(filename) = filename {
             attachments.push(Attachment::File(
                 b.id,
                 filename.to_string(),
@@ -115,9 +328,6 @@ pub fn body_attachments(bodys: &Vec<Body>) -> Result<(String, Vec<Attachment>),
             ));
         }
     }
-    if body.is_empty() {
-        body = html_to_text(&body_html)?;
-    }
     if !body_html.is_empty() {
         attachments.push(Attachment::Html(
             body_html,
@@ -162,8 +372,15 @@ pub fn parse_thread(
     Ok(())
 }
 
-pub fn parse_messages(search_term: &str) -> Result<Vec<Message>, failure::Error> {
-    debug!("parse_messages: {}", search_term);
+pub fn has_attachments(bodys: &[Body]) -> bool {
+    bodys.iter().any(|b| match &b.content {
+        Some(Content::Array(bs)) => has_attachments(bs),
+        _ => b.filename.is_some(),
+    })
+}
+
+pub fn parse_messages(search_term: &str, sort: &str) -> Result<Vec<Message>, failure::Error> {
+    debug!("parse_messages: {} {}", search_term, sort);
 
     let mut result: Vec<Message> = vec![];
 
@@ -171,15 +388,25 @@ pub fn parse_messages(search_term: &str) -> Result<Vec<Message>, failure::Error>
         .arg("show")
         .arg("--format=json")
         .arg("--include-html")
+        .arg(format!("--sort={}", sort))
         .arg(search_term)
         .output()?;
 
     let threadset: Vec<Vec<Vec<Node>>> = serde_json::from_slice(&output.stdout)?;
 
-    for threads in threadset.iter() {
+    for (i, threads) in threadset.iter().enumerate() {
+        let start = result.len();
         for thread in threads.iter() {
             parse_thread(thread, 0, &mut result)?;
         }
+        for msg in result[start..].iter_mut() {
+            msg.thread = i;
+            msg.size = msg
+                .filename
+                .first()
+                .and_then(|path| std::fs::metadata(path).ok())
+                .map(|meta| meta.len());
+        }
     }
 
     Ok(result)
@@ -203,3 +430,129 @@ pub fn insert_message(data: &[u8]) -> Result<(), failure::Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bodys(json: &str) -> Vec<Body> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn declared_charsets() {
+        assert_eq!(
+            decode(b"p\xe4iv\xe4\xe4", Some("ISO-8859-1")),
+            Some("päivää".into())
+        );
+        assert_eq!(
+            decode(b"\xa4 \xbd", Some("iso-8859-15")),
+            Some("€ œ".into())
+        );
+        assert_eq!(
+            decode(b"\x93quoted\x94 \x80", Some(" windows-1252 ")),
+            Some("“quoted” €".into())
+        );
+        assert_eq!(
+            decode(b"\x1b$B$3$s$K$A$O\x1b(B", Some("ISO-2022-JP")),
+            Some("こんにちは".into())
+        );
+        assert_eq!(decode(b"", Some("utf-8")), Some("".into()));
+    }
+
+    #[test]
+    fn undecodable_parts() {
+        assert_eq!(decode(b"x", Some("no-such-charset")), None);
+        assert_eq!(decode(b"\xff\xfe", Some("utf-8")), None);
+        assert_eq!(decode(b"\x1b$B\xff", Some("iso-2022-jp")), None);
+    }
+
+    #[test]
+    fn undeclared_charset_tries_utf8() {
+        assert_eq!(decode("päivää".as_bytes(), None), Some("päivää".into()));
+        assert_eq!(decode(b"p\xe4iv\xe4\xe4", None), Some("päivää".into()));
+    }
+
+    #[test]
+    fn missing_parts_get_placeholder() {
+        let b =
+            bodys(r#"[{"id": 3, "content-type": "text/plain", "content-charset": "iso-8859-1"}]"#);
+        assert_eq!(
+            decode_part("no-such-message@example.com", &b[0]),
+            "[nutt: unable to decode part 3 (text/plain; charset=iso-8859-1)]\n"
+        );
+    }
+
+    #[test]
+    fn parts_are_collected() {
+        let b = bodys(
+            r#"[{"id": 1, "content-type": "multipart/mixed", "content": [
+                {"id": 2, "content-type": "multipart/alternative", "content": [
+                    {"id": 3, "content-type": "text/plain", "content": "hei\n"},
+                    {"id": 4, "content-type": "text/html", "content": "<p>hei</p>"}
+                ]},
+                {"id": 5, "content-type": "application/pdf", "filename": "ä.pdf"},
+                {"id": 6, "content-type": "text/plain", "content-charset": "iso-8859-1",
+                 "content": "p\ufffdiv\ufffd"}
+            ]}]"#,
+        );
+        let (body, atts) =
+            collect_parts("no-such-message@example.com", &b, &HashMap::new()).unwrap();
+        // wrongly guessed charset is decoded again, here the part can't be read
+        assert!(body.starts_with("hei\n[nutt: unable to decode part 6"));
+        match &atts[..] {
+            [Attachment::Html(html, _), Attachment::File(5, name, _, _)] => {
+                assert_eq!(name, "ä.pdf");
+                assert_eq!(html, "<p>hei</p>");
+            }
+            _ => panic!("unexpected attachments"),
+        }
+    }
+
+    #[test]
+    fn html_renderer_output() {
+        assert_eq!(
+            html_to_text("<p>x</p>", 40, Some("cat")).unwrap(),
+            "<p>x</p>"
+        );
+        assert_eq!(html_to_text("", 40, Some("echo %w")).unwrap(), "40\n");
+        assert_eq!(html_to_text("<p>x</p>", 40, None).unwrap(), "x\n");
+    }
+
+    #[test]
+    fn failed_html_renderer_falls_back() {
+        let renderer = Some("echo partial; echo broken >&2; exit 3");
+        assert_eq!(html_to_text("<p>x</p>", 40, renderer).unwrap(), "x\n");
+    }
+
+    #[test]
+    fn hard_broken_text_is_not_flowed() {
+        let b = bodys(r#"[{"id": 1, "content-type": "text/plain", "content": "päivää\nline\n"}]"#);
+        assert!(!may_be_flowed(&b));
+        assert!(!may_be_flowed(&vec![]));
+    }
+
+    #[test]
+    fn soft_breaks_in_nested_parts_are_found() {
+        let b = bodys(
+            r#"[{"id": 1, "content-type": "multipart/mixed", "content": [
+                {"id": 2, "content-type": "text/plain", "content": "soft \nbreak\n"},
+                {"id": 3, "content-type": "text/html", "content": "<p> </p>"}
+            ]}]"#,
+        );
+        assert!(may_be_flowed(&b));
+        let b =
+            bodys(r#"[{"id": 1, "content-type": "text/plain", "content": " From stuffed\r\n"}]"#);
+        assert!(may_be_flowed(&b));
+    }
+
+    #[test]
+    fn attachments_and_missing_content() {
+        let b = bodys(
+            r#"[{"id": 1, "content-type": "text/plain", "filename": "a.txt", "content": "x \n"}]"#,
+        );
+        assert!(!may_be_flowed(&b));
+        let b = bodys(r#"[{"id": 1, "content-type": "text/plain"}]"#);
+        assert!(may_be_flowed(&b));
+    }
+}
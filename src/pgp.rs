@@ -0,0 +1,121 @@
+use failure::bail;
+use log::*;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+fn gpg(args: &[&str], input: &[u8]) -> Result<Vec<u8>, failure::Error> {
+    debug!("gpg: {:?}", args);
+
+    let mut child = Command::new("gpg")
+        .arg("--batch")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // written from a thread so large output can't block the input
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or(failure::format_err!("Failed to run gpg"))?;
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()?;
+    let written = writer
+        .join()
+        .map_err(|_| failure::format_err!("Failed to write to gpg"))?;
+    if !output.status.success() {
+        bail!(
+            "gpg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    written?;
+
+    Ok(output.stdout)
+}
+
+// armored detached signature
+pub fn sign(data: &[u8], key: &str) -> Result<Vec<u8>, failure::Error> {
+    gpg(
+        &[
+            "--armor",
+            "--detach-sign",
+            "--digest-algo",
+            "SHA256",
+            "--local-user",
+            key,
+        ],
+        data,
+    )
+}
+
+// armored ciphertext, also signed with 'sign_key' if given
+// 'key_files' are used for recipients not in the keyring
+pub fn encrypt(
+    data: &[u8],
+    recipients: &[String],
+    key_files: &[PathBuf],
+    sign_key: Option<&str>,
+) -> Result<Vec<u8>, failure::Error> {
+    let mut args = vec!["--armor", "--encrypt"];
+    if let Some(key) = sign_key {
+        args.extend(&["--sign", "--local-user", key]);
+    }
+    for r in recipients {
+        args.extend(&["--recipient", r.as_str()]);
+    }
+    for f in key_files {
+        args.push("--recipient-file");
+        args.push(f.to_str().unwrap_or_default());
+    }
+
+    gpg(&args, data)
+}
+
+// binary public key, empty if key is not found
+pub fn export(key: &str) -> Result<Vec<u8>, failure::Error> {
+    gpg(
+        &["--export-options", "export-minimal", "--export", key],
+        &[],
+    )
+}
+
+pub fn has_key(address: &str) -> bool {
+    let output = Command::new("gpg")
+        .arg("--batch")
+        .arg("--with-colons")
+        .arg("--list-keys")
+        .arg(format!("<{}>", address))
+        .stderr(Stdio::null())
+        .output();
+
+    match output {
+        // skip revoked, expired, disabled and invalid keys
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.split(':').collect::<Vec<&str>>())
+            .any(|fields| {
+                fields[0] == "pub"
+                    && !matches!(
+                        fields.get(1),
+                        Some(&"r") | Some(&"e") | Some(&"d") | Some(&"i")
+                    )
+            }),
+        Err(e) => {
+            error!("gpg --list-keys: {}", e);
+            false
+        }
+    }
+}
+
+pub fn missing_keys(addresses: &[String]) -> Vec<String> {
+    addresses
+        .iter()
+        .filter(|addr| !has_key(addr))
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,248 @@
+use crate::prompt::Prompt;
+use log::*;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use termion::event::Key;
+
+const HISTORY_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    // notmuch raw format, mbox for several messages
+    Raw,
+    Decoded,
+    // mbox of all messages in thread
+    Thread,
+}
+
+impl Mode {
+    pub fn next(self) -> Mode {
+        match self {
+            Mode::Raw => Mode::Decoded,
+            Mode::Decoded => Mode::Thread,
+            Mode::Thread => Mode::Raw,
+        }
+    }
+
+    pub fn label(self) -> String {
+        let name = match self {
+            Mode::Raw => "raw",
+            Mode::Decoded => "decoded",
+            Mode::Thread => "thread",
+        };
+        format!("Pipe {} to: ", name)
+    }
+}
+
+// previous commands, newest last
+#[derive(Default)]
+pub struct History {
+    entries: Vec<String>,
+    pos: Option<usize>,
+    // file the history is saved to, kept in memory only when None
+    path: Option<PathBuf>,
+}
+
+fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("nutt").join("pipe_history"))
+}
+
+impl History {
+    pub fn load() -> History {
+        let path = history_path();
+        let entries = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|data| data.lines().map(|l| l.to_string()).collect())
+            .unwrap_or_default();
+
+        History {
+            entries,
+            pos: None,
+            path,
+        }
+    }
+
+    pub fn add(&mut self, command: &str) {
+        self.pos = None;
+        if command.trim().is_empty() {
+            return;
+        }
+        self.entries.retain(|e| e != command);
+        self.entries.push(command.to_string());
+        if self.entries.len() > HISTORY_SIZE {
+            self.entries.remove(0);
+        }
+
+        if let Err(e) = self.save() {
+            error!("Failed to save pipe history: {}", e);
+        }
+    }
+
+    fn save(&self) -> Result<(), failure::Error> {
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, self.entries.join("\n"))?;
+        }
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Option<&str> {
+        let pos = match self.pos {
+            Some(0) => 0,
+            Some(pos) => pos - 1,
+            None => self.entries.len().checked_sub(1)?,
+        };
+        self.pos = Some(pos);
+        self.entries.get(pos).map(|e| e.as_str())
+    }
+
+    fn next(&mut self) -> Option<&str> {
+        let pos = self.pos? + 1;
+        match pos < self.entries.len() {
+            true => {
+                self.pos = Some(pos);
+                self.entries.get(pos).map(|e| e.as_str())
+            }
+            _ => {
+                self.pos = None;
+                Some("")
+            }
+        }
+    }
+}
+
+// Tab cycles mode, Up/Down walk history, false for keys left to prompt
+pub fn handle_key(key: &Key, prompt: &mut Prompt, mode: &mut Mode, history: &mut History) -> bool {
+    match key {
+        Key::Char('\t') => {
+            *mode = mode.next();
+            prompt.label = mode.label();
+        }
+        Key::Up => {
+            if let Some(command) = history.prev() {
+                prompt.set_input(command);
+            }
+        }
+        Key::Down => {
+            if let Some(command) = history.next() {
+                prompt.set_input(command);
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+// run shell command with data in stdin, returns stdout and stderr
+pub fn run(command: &str, data: &[u8]) -> Result<(String, ExitStatus), failure::Error> {
+    debug!("pipe::run: {}", command);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // written from a thread so large output can't block the input,
+    // command may also exit without reading all of it
+    let writer = child.stdin.take().map(|mut stdin| {
+        let data = data.to_vec();
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(&data);
+        })
+    });
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    let mut result = String::from_utf8_lossy(&output.stdout).to_string();
+    result.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    Ok((result, output.status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_unique_commands_newest_last() {
+        let mut history = History::default();
+        history.add("grep a");
+        history.add("  ");
+        history.add("wc -l");
+        history.add("grep a");
+        assert_eq!(history.entries, vec!["wc -l", "grep a"]);
+
+        for i in 0..HISTORY_SIZE + 5 {
+            history.add(&format!("cmd {}", i));
+        }
+        assert_eq!(history.entries.len(), HISTORY_SIZE);
+        assert_eq!(history.entries[0], "cmd 5");
+    }
+
+    #[test]
+    fn history_is_walked_both_ways() {
+        let mut history = History::default();
+        assert_eq!(history.prev(), None);
+        assert_eq!(history.next(), None);
+
+        history.add("first");
+        history.add("second");
+        assert_eq!(history.prev(), Some("second"));
+        assert_eq!(history.prev(), Some("first"));
+        // stays at the oldest entry
+        assert_eq!(history.prev(), Some("first"));
+        assert_eq!(history.next(), Some("second"));
+        // past the newest entry input is cleared
+        assert_eq!(history.next(), Some(""));
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn keys_switch_mode_and_history() {
+        let mut history = History::default();
+        history.add("less");
+        let mut mode = Mode::Raw;
+        let mut prompt = Prompt::new(&mode.label(), "");
+
+        assert!(handle_key(
+            &Key::Char('\t'),
+            &mut prompt,
+            &mut mode,
+            &mut history
+        ));
+        assert_eq!(mode, Mode::Decoded);
+        assert_eq!(prompt.label, "Pipe decoded to: ");
+        assert!(handle_key(&Key::Up, &mut prompt, &mut mode, &mut history));
+        assert_eq!(prompt.input(), "less");
+        assert!(handle_key(&Key::Down, &mut prompt, &mut mode, &mut history));
+        assert_eq!(prompt.input(), "");
+        assert!(!handle_key(
+            &Key::Char('x'),
+            &mut prompt,
+            &mut mode,
+            &mut history
+        ));
+        assert_eq!(Mode::Thread.next(), Mode::Raw);
+    }
+
+    #[test]
+    fn output_and_status_are_returned() {
+        let (output, status) = run("tr a-z A-Z; echo err >&2", "päivä\n".as_bytes()).unwrap();
+        assert_eq!(output, "PäIVä\nerr\n");
+        assert!(status.success());
+
+        // command not reading its input still finishes
+        let (output, status) = run("exit 3", &vec![b'x'; 1 << 20]).unwrap();
+        assert_eq!(output, "");
+        assert_eq!(status.code(), Some(3));
+    }
+}
@@ -0,0 +1,369 @@
+use crate::width;
+use termion::event::{Event, Key};
+use unicode_segmentation::UnicodeSegmentation;
+
+const KILL_RING_SIZE: usize = 20;
+
+// terminal wraps pasted text in these when bracketed paste is on
+pub const PASTE_ON: &str = "\x1b[?2004h";
+pub const PASTE_OFF: &str = "\x1b[?2004l";
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+// ctrl-left and ctrl-right, termion leaves these unparsed
+const WORD_LEFT: &[u8] = b"\x1b[1;5D";
+const WORD_RIGHT: &[u8] = b"\x1b[1;5C";
+
+pub enum PromptEvent {
+    Submit(String),
+    Cancel,
+    Edit,
+}
+
+// text killed with ctrl-w/u/k and alt-d, shared by all prompts
+#[derive(Default)]
+pub struct KillRing {
+    entries: Vec<String>,
+}
+
+impl KillRing {
+    fn push(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.entries.push(text.to_string());
+        if self.entries.len() > KILL_RING_SIZE {
+            self.entries.remove(0);
+        }
+    }
+
+    // n:th newest entry, wraps around
+    fn get(&self, n: usize) -> Option<&str> {
+        match self.entries.len() {
+            0 => None,
+            len => Some(&self.entries[len - 1 - n % len]),
+        }
+    }
+}
+
+// single line input shown at the bottom of a view
+pub struct Prompt {
+    pub label: String,
+    input: String,
+    // byte offset in input, always on a grapheme boundary
+    pos: usize,
+    // start of last yanked text and its kill ring index, for alt-y
+    yanked: Option<(usize, usize)>,
+    // inside bracketed paste
+    pasting: bool,
+}
+
+impl Prompt {
+    pub fn new(label: &str, input: &str) -> Self {
+        Prompt {
+            label: label.to_string(),
+            input: input.to_string(),
+            pos: input.len(),
+            yanked: None,
+            pasting: false,
+        }
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    // keys are pasted text, not commands
+    pub fn is_pasting(&self) -> bool {
+        self.pasting
+    }
+
+    // replace input, cursor to end
+    pub fn set_input(&mut self, input: &str) {
+        self.input = input.to_string();
+        self.pos = input.len();
+        self.yanked = None;
+    }
+
+    fn prev_grapheme(&self) -> usize {
+        self.input[..self.pos]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_grapheme(&self) -> usize {
+        self.input[self.pos..]
+            .graphemes(true)
+            .next()
+            .map_or(self.pos, |g| self.pos + g.len())
+    }
+
+    // start of word before cursor, words separated by 'is_separator'
+    fn word_start<F: Fn(char) -> bool>(&self, is_separator: F) -> usize {
+        let before = self.input[..self.pos].trim_end_matches(&is_separator);
+        before.rfind(is_separator).map_or(0, |i| {
+            i + before[i..].chars().next().map_or(1, |c| c.len_utf8())
+        })
+    }
+
+    fn word_end(&self) -> usize {
+        let after = &self.input[self.pos..];
+        let skipped = after.len()
+            - after
+                .trim_start_matches(|c: char| !c.is_alphanumeric())
+                .len();
+        let word = &after[skipped..];
+        self.pos
+            + skipped
+            + word
+                .find(|c: char| !c.is_alphanumeric())
+                .unwrap_or(word.len())
+    }
+
+    fn insert(&mut self, text: &str) {
+        self.input.insert_str(self.pos, text);
+        self.pos += text.len();
+    }
+
+    // remove text between cursor and 'to', killed text goes to kill ring
+    fn delete(&mut self, to: usize, kill_ring: Option<&mut KillRing>) {
+        let (start, end) = (self.pos.min(to), self.pos.max(to));
+        if let Some(kill_ring) = kill_ring {
+            kill_ring.push(&self.input[start..end]);
+        }
+        self.input.replace_range(start..end, "");
+        self.pos = start;
+    }
+
+    // alt-y replaces just yanked text with the previous kill
+    fn yank(&mut self, kill_ring: &KillRing, pop: bool) {
+        let (start, n) = match (pop, self.yanked) {
+            (true, Some((start, n))) => {
+                self.input.replace_range(start..self.pos, "");
+                self.pos = start;
+                (start, n + 1)
+            }
+            (true, None) => return,
+            _ => (self.pos, 0),
+        };
+        if let Some(text) = kill_ring.get(n) {
+            self.insert(text);
+            self.yanked = Some((start, n));
+        }
+    }
+
+    pub fn handle_event(&mut self, event: Event, kill_ring: &mut KillRing) -> PromptEvent {
+        match event {
+            Event::Key(key) => return self.handle_key(key, kill_ring),
+            Event::Unsupported(seq) => {
+                self.yanked = None;
+                match seq.as_slice() {
+                    PASTE_START => self.pasting = true,
+                    PASTE_END => self.pasting = false,
+                    WORD_LEFT => self.pos = self.word_start(|c| !c.is_alphanumeric()),
+                    WORD_RIGHT => self.pos = self.word_end(),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        PromptEvent::Edit
+    }
+
+    pub fn handle_key(&mut self, key: Key, kill_ring: &mut KillRing) -> PromptEvent {
+        let yanked = self.yanked.take();
+
+        // pasted text is inserted as is, newlines as spaces
+        if self.pasting {
+            match key {
+                // a lost paste end must not leave the prompt stuck
+                Key::Esc | Key::Ctrl('c') => self.pasting = false,
+                Key::Char('\n') | Key::Char('\r') => self.insert(" "),
+                Key::Char(ch) => self.insert(&ch.to_string()),
+                _ => {}
+            }
+            if self.pasting {
+                return PromptEvent::Edit;
+            }
+        }
+
+        match key {
+            Key::Char('\n') => return PromptEvent::Submit(self.input.to_string()),
+            Key::Esc | Key::Ctrl('c') | Key::Ctrl('g') => return PromptEvent::Cancel,
+
+            Key::Left | Key::Ctrl('b') => self.pos = self.prev_grapheme(),
+            Key::Right | Key::Ctrl('f') => self.pos = self.next_grapheme(),
+            Key::Home | Key::Ctrl('a') => self.pos = 0,
+            Key::End | Key::Ctrl('e') => self.pos = self.input.len(),
+            Key::Alt('b') => self.pos = self.word_start(|c| !c.is_alphanumeric()),
+            Key::Alt('f') => self.pos = self.word_end(),
+
+            Key::Backspace | Key::Ctrl('h') => self.delete(self.prev_grapheme(), None),
+            Key::Delete | Key::Ctrl('d') => self.delete(self.next_grapheme(), None),
+            Key::Ctrl('w') => self.delete(self.word_start(char::is_whitespace), Some(kill_ring)),
+            Key::Alt('\x7f') => {
+                self.delete(self.word_start(|c| !c.is_alphanumeric()), Some(kill_ring))
+            }
+            Key::Alt('d') => self.delete(self.word_end(), Some(kill_ring)),
+            Key::Ctrl('u') => self.delete(0, Some(kill_ring)),
+            Key::Ctrl('k') => self.delete(self.input.len(), Some(kill_ring)),
+            Key::Ctrl('y') => self.yank(kill_ring, false),
+            Key::Alt('y') => {
+                self.yanked = yanked;
+                self.yank(kill_ring, true);
+            }
+
+            Key::Char(ch) => self.insert(&ch.to_string()),
+            _ => {}
+        }
+        PromptEvent::Edit
+    }
+
+    pub fn text(&self) -> String {
+        format!("{}{}", self.label, self.input)
+    }
+
+    // cursor column relative to prompt start
+    pub fn cursor(&self) -> u16 {
+        (width::width(&self.label) + width::width(&self.input[..self.pos])) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(p: &mut Prompt, kill_ring: &mut KillRing, keys: &[Key]) {
+        for key in keys {
+            p.handle_key(*key, kill_ring);
+        }
+    }
+
+    #[test]
+    fn editing_moves_by_graphemes() {
+        let mut kill_ring = KillRing::default();
+        let mut p = Prompt::new("> ", "ae\u{301}日");
+        keys(
+            &mut p,
+            &mut kill_ring,
+            &[Key::Left, Key::Left, Key::Backspace],
+        );
+        assert_eq!(p.input(), "e\u{301}日");
+        keys(&mut p, &mut kill_ring, &[Key::Delete, Key::Char('x')]);
+        assert_eq!(p.input(), "x日");
+        keys(
+            &mut p,
+            &mut kill_ring,
+            &[Key::Home, Key::Backspace, Key::End, Key::Delete],
+        );
+        assert_eq!(p.input(), "x日");
+    }
+
+    #[test]
+    fn empty_input_is_safe() {
+        let mut kill_ring = KillRing::default();
+        let mut p = Prompt::new("", "");
+        let all = [
+            Key::Left,
+            Key::Right,
+            Key::Backspace,
+            Key::Delete,
+            Key::Ctrl('w'),
+            Key::Alt('d'),
+            Key::Ctrl('k'),
+            Key::Ctrl('y'),
+            Key::Alt('y'),
+        ];
+        keys(&mut p, &mut kill_ring, &all);
+        assert_eq!(p.input(), "");
+        assert_eq!(kill_ring.get(0), None);
+    }
+
+    #[test]
+    fn words_are_killed_and_yanked() {
+        let mut kill_ring = KillRing::default();
+        let mut p = Prompt::new("", "tag:päivä foo");
+        keys(&mut p, &mut kill_ring, &[Key::Ctrl('w')]);
+        assert_eq!(p.input(), "tag:päivä ");
+        keys(&mut p, &mut kill_ring, &[Key::Alt('\x7f')]);
+        assert_eq!(p.input(), "tag:");
+        keys(&mut p, &mut kill_ring, &[Key::Ctrl('y')]);
+        assert_eq!(p.input(), "tag:päivä ");
+        // alt-y replaces the yank with the previous kill
+        keys(&mut p, &mut kill_ring, &[Key::Alt('y')]);
+        assert_eq!(p.input(), "tag:foo");
+        keys(&mut p, &mut kill_ring, &[Key::Alt('y')]);
+        assert_eq!(p.input(), "tag:päivä ");
+        // not right after a yank
+        keys(&mut p, &mut kill_ring, &[Key::Left, Key::Alt('y')]);
+        assert_eq!(p.input(), "tag:päivä ");
+    }
+
+    #[test]
+    fn kill_to_line_ends() {
+        let mut kill_ring = KillRing::default();
+        let mut p = Prompt::new("", "one two");
+        keys(&mut p, &mut kill_ring, &[Key::Alt('b'), Key::Ctrl('k')]);
+        assert_eq!(p.input(), "one ");
+        keys(&mut p, &mut kill_ring, &[Key::Ctrl('u')]);
+        assert_eq!(p.input(), "");
+        assert_eq!(kill_ring.get(0), Some("one "));
+        assert_eq!(kill_ring.get(1), Some("two"));
+        assert_eq!(kill_ring.get(2), Some("one "));
+    }
+
+    #[test]
+    fn kill_ring_is_capped() {
+        let mut kill_ring = KillRing::default();
+        for i in 0..KILL_RING_SIZE + 5 {
+            kill_ring.push(&i.to_string());
+        }
+        kill_ring.push("");
+        assert_eq!(kill_ring.entries.len(), KILL_RING_SIZE);
+        assert_eq!(kill_ring.get(0), Some("24"));
+    }
+
+    #[test]
+    fn pasted_keys_are_text() {
+        let mut kill_ring = KillRing::default();
+        let mut p = Prompt::new("", "");
+        p.handle_event(Event::Unsupported(PASTE_START.to_vec()), &mut kill_ring);
+        assert!(p.is_pasting());
+        keys(
+            &mut p,
+            &mut kill_ring,
+            &[Key::Char('a'), Key::Char('\n'), Key::Ctrl('u')],
+        );
+        match p.handle_key(Key::Char('\n'), &mut kill_ring) {
+            PromptEvent::Edit => {}
+            _ => panic!("newline submitted while pasting"),
+        }
+        p.handle_event(Event::Unsupported(PASTE_END.to_vec()), &mut kill_ring);
+        assert!(!p.is_pasting());
+        assert_eq!(p.input(), "a  ");
+    }
+
+    #[test]
+    fn escape_ends_paste() {
+        let mut kill_ring = KillRing::default();
+        for key in &[Key::Esc, Key::Ctrl('c')] {
+            let mut p = Prompt::new("", "");
+            p.handle_event(Event::Unsupported(PASTE_START.to_vec()), &mut kill_ring);
+            match p.handle_key(*key, &mut kill_ring) {
+                PromptEvent::Cancel => {}
+                _ => panic!("{:?} didn't cancel the paste", key),
+            }
+            assert!(!p.is_pasting());
+        }
+    }
+
+    #[test]
+    fn cursor_counts_columns() {
+        let mut kill_ring = KillRing::default();
+        let mut p = Prompt::new("Haku: ", "日本");
+        assert_eq!(p.cursor(), 10);
+        keys(&mut p, &mut kill_ring, &[Key::Left]);
+        assert_eq!(p.cursor(), 8);
+        assert_eq!(p.text(), "Haku: 日本");
+    }
+}
@@ -0,0 +1,168 @@
+// quote depth of line, '> > text' and '>> text' are both 2
+pub fn depth(line: &str) -> usize {
+    let mut depth = 0;
+    for ch in line.chars() {
+        match ch {
+            '>' => depth += 1,
+            ' ' | '\t' if depth > 0 => {}
+            _ => break,
+        }
+    }
+    depth
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    // run of lines quoted at least 'level' deep
+    Quote(usize),
+    Signature,
+}
+
+// foldable range of lines, 'end' exclusive
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub kind: Kind,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Block {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn summary(&self) -> String {
+        match self.kind {
+            Kind::Quote(level) => format!("{} [{} quoted lines]", ">".repeat(level), self.len()),
+            Kind::Signature => format!("-- [signature, {} lines]", self.len() - 1),
+        }
+    }
+}
+
+// quote blocks outer levels first, signature last
+pub fn blocks(lines: &[&str]) -> Vec<Block> {
+    let signature = lines.iter().rposition(|l| *l == "-- ");
+    let end = signature.unwrap_or(lines.len());
+    let depths: Vec<usize> = lines[..end].iter().map(|l| depth(l)).collect();
+    let max = depths.iter().cloned().max().unwrap_or(0);
+
+    let mut result = vec![];
+    for level in 1..=max {
+        let mut start = None;
+        for (i, d) in depths.iter().chain(std::iter::once(&0)).enumerate() {
+            match (start, *d >= level) {
+                (None, true) => start = Some(i),
+                (Some(s), false) => {
+                    result.push(Block {
+                        kind: Kind::Quote(level),
+                        start: s,
+                        end: i,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some(start) = signature {
+        result.push(Block {
+            kind: Kind::Signature,
+            start,
+            end: lines.len(),
+        });
+    }
+
+    result
+}
+
+// signature and long quotes with nothing but blank lines after them
+pub fn default_folded(lines: &[&str], blocks: &[Block]) -> Vec<bool> {
+    let text_end = blocks
+        .iter()
+        .find(|b| b.kind == Kind::Signature)
+        .map_or(lines.len(), |b| b.start);
+
+    blocks
+        .iter()
+        .map(|b| match b.kind {
+            Kind::Signature => true,
+            Kind::Quote(1) => {
+                b.len() > 3 && lines[b.end..text_end].iter().all(|l| l.trim().is_empty())
+            }
+            Kind::Quote(_) => false,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(blocks: &[Block]) -> Vec<(Kind, usize, usize)> {
+        blocks
+            .iter()
+            .map(|b| (b.kind.clone(), b.start, b.end))
+            .collect()
+    }
+
+    #[test]
+    fn depth_counts_spaced_markers() {
+        assert_eq!(depth("> > text"), 2);
+        assert_eq!(depth(">>text"), 2);
+        assert_eq!(depth(" > not quoted"), 0);
+        assert_eq!(depth("ä > b"), 0);
+        assert_eq!(depth(""), 0);
+    }
+
+    #[test]
+    fn nested_quotes_and_signature_are_found() {
+        let lines = vec![
+            "Hei,", "> eka", ">> toka", "> eka", "", "vastaus", "-- ", "Ämmä",
+        ];
+        assert_eq!(
+            ranges(&blocks(&lines)),
+            vec![
+                (Kind::Quote(1), 1, 4),
+                (Kind::Quote(2), 2, 3),
+                (Kind::Signature, 6, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn quote_lines_after_signature_belong_to_it() {
+        let lines = vec!["> a", "-- ", "> b"];
+        assert_eq!(
+            ranges(&blocks(&lines)),
+            vec![(Kind::Quote(1), 0, 1), (Kind::Signature, 1, 3)]
+        );
+    }
+
+    #[test]
+    fn empty_text_has_no_blocks() {
+        assert!(blocks(&[]).is_empty());
+        assert!(blocks(&["", "plain"]).is_empty());
+        assert!(default_folded(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn trailing_long_quotes_and_signature_fold() {
+        let lines = vec!["reply", "> 1", "> 2", "> 3", "> 4", "", "-- ", "me"];
+        let found = blocks(&lines);
+        assert_eq!(default_folded(&lines, &found), vec![true, true]);
+
+        // quote followed by text stays open, as does a short one
+        let lines = vec!["> 1", "> 2", "> 3", "> 4", "reply", "> short"];
+        let found = blocks(&lines);
+        assert_eq!(default_folded(&lines, &found), vec![false, false]);
+    }
+
+    #[test]
+    fn summaries_count_lines() {
+        let lines = vec![">> a", ">> b", "-- ", "x", "y"];
+        let found = blocks(&lines);
+        assert_eq!(found[0].summary(), "> [2 quoted lines]");
+        assert_eq!(found[1].summary(), ">> [2 quoted lines]");
+        assert_eq!(found[2].summary(), "-- [signature, 2 lines]");
+    }
+}
@@ -0,0 +1,179 @@
+use termion::event::Key;
+
+// visible window of 'len' lines content, 'height' lines tall,
+// resized every frame so offset stays valid when terminal size changes
+#[derive(Default, Debug, Clone, Copy)]
+pub struct Viewport {
+    // first visible line
+    pub offset: u16,
+    len: u16,
+    height: u16,
+}
+
+impl Viewport {
+    pub fn resize(&mut self, len: u16, height: u16) {
+        self.len = len;
+        self.height = height;
+        self.offset = std::cmp::min(self.offset, self.max());
+    }
+
+    pub fn max(&self) -> u16 {
+        self.len.saturating_sub(self.height)
+    }
+
+    pub fn scroll_to(&mut self, line: u16) {
+        self.offset = std::cmp::min(line, self.max());
+    }
+
+    pub fn scroll_by(&mut self, lines: i32) {
+        let line = std::cmp::max(self.offset as i32 + lines, 0);
+        self.scroll_to(std::cmp::min(line, u16::MAX as i32) as u16);
+    }
+
+    // least scrolling that brings line into view
+    pub fn show(&mut self, line: u16) {
+        if line < self.offset {
+            self.offset = line;
+        } else if self.height > 0 && line >= self.offset + self.height {
+            self.scroll_to(line - (self.height - 1));
+        }
+    }
+
+    // vim's zz, zt and zb: line to center, top or bottom of view
+    pub fn align(&mut self, line: u16, key: char) -> bool {
+        let offset = match key {
+            'z' => line.saturating_sub(self.height / 2),
+            't' => line,
+            'b' => line.saturating_add(1).saturating_sub(self.height),
+            _ => return false,
+        };
+        self.scroll_to(offset);
+        true
+    }
+
+    // ctrl-d/u half a page, ctrl-f/b and page down/up a page minus
+    // two lines of context, returns lines asked for so that a
+    // selection can move along
+    pub fn page_key(&mut self, key: &Key) -> Option<i32> {
+        let half = std::cmp::max(self.height / 2, 1) as i32;
+        let page = std::cmp::max(self.height.saturating_sub(2), 1) as i32;
+        let lines = match key {
+            Key::Ctrl('d') => half,
+            Key::Ctrl('u') => -half,
+            Key::Ctrl('f') | Key::PageDown => page,
+            Key::Ctrl('b') | Key::PageUp => -page,
+            _ => return None,
+        };
+        self.scroll_by(lines);
+        Some(lines)
+    }
+
+    // position like less and vim show it
+    pub fn indicator(&self) -> String {
+        match (self.max(), self.offset) {
+            (0, _) => "All".to_string(),
+            (_, 0) => "Top".to_string(),
+            (max, offset) if offset >= max => "Bot".to_string(),
+            (max, offset) => format!("{}%", offset as u32 * 100 / max as u32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(len: u16, height: u16) -> Viewport {
+        let mut viewport = Viewport::default();
+        viewport.resize(len, height);
+        viewport
+    }
+
+    #[test]
+    fn resize_keeps_offset_valid() {
+        let mut v = viewport(100, 10);
+        v.scroll_to(95);
+        assert_eq!(v.offset, 90);
+        v.resize(100, 20);
+        assert_eq!(v.offset, 80);
+        v.resize(5, 20);
+        assert_eq!(v.offset, 0);
+        v.resize(0, 0);
+        assert_eq!(v.offset, 0);
+    }
+
+    #[test]
+    fn scroll_by_stays_in_range() {
+        let mut v = viewport(30, 10);
+        v.scroll_by(-5);
+        assert_eq!(v.offset, 0);
+        v.scroll_by(i32::MAX);
+        assert_eq!(v.offset, 20);
+        v.scroll_by(-3);
+        assert_eq!(v.offset, 17);
+    }
+
+    #[test]
+    fn show_scrolls_least() {
+        let mut v = viewport(100, 10);
+        v.show(5);
+        assert_eq!(v.offset, 0);
+        v.show(10);
+        assert_eq!(v.offset, 1);
+        v.show(0);
+        assert_eq!(v.offset, 0);
+        v.show(u16::MAX);
+        assert_eq!(v.offset, 90);
+        // nothing visible, nothing to scroll
+        let mut v = viewport(100, 0);
+        v.show(50);
+        assert_eq!(v.offset, 0);
+    }
+
+    #[test]
+    fn align_line() {
+        let mut v = viewport(100, 10);
+        assert!(v.align(50, 'z'));
+        assert_eq!(v.offset, 45);
+        assert!(v.align(50, 't'));
+        assert_eq!(v.offset, 50);
+        assert!(v.align(50, 'b'));
+        assert_eq!(v.offset, 41);
+        assert!(v.align(2, 'z'));
+        assert_eq!(v.offset, 0);
+        assert!(v.align(u16::MAX, 'b'));
+        assert_eq!(v.offset, 90);
+        assert!(!v.align(50, 'x'));
+        assert_eq!(v.offset, 90);
+    }
+
+    #[test]
+    fn page_keys() {
+        let mut v = viewport(100, 10);
+        assert_eq!(v.page_key(&Key::Ctrl('d')), Some(5));
+        assert_eq!(v.page_key(&Key::PageDown), Some(8));
+        assert_eq!(v.offset, 13);
+        assert_eq!(v.page_key(&Key::Ctrl('b')), Some(-8));
+        assert_eq!(v.page_key(&Key::Ctrl('u')), Some(-5));
+        assert_eq!(v.offset, 0);
+        assert_eq!(v.page_key(&Key::Char('j')), None);
+        // tiny views still move
+        let mut v = viewport(100, 1);
+        assert_eq!(v.page_key(&Key::Ctrl('d')), Some(1));
+        assert_eq!(v.page_key(&Key::Ctrl('f')), Some(1));
+    }
+
+    #[test]
+    fn indicator_like_less() {
+        let mut v = viewport(10, 10);
+        assert_eq!(v.indicator(), "All");
+        v.resize(0, 0);
+        assert_eq!(v.indicator(), "All");
+        v.resize(110, 10);
+        assert_eq!(v.indicator(), "Top");
+        v.scroll_to(50);
+        assert_eq!(v.indicator(), "50%");
+        v.scroll_to(100);
+        assert_eq!(v.indicator(), "Bot");
+    }
+}
@@ -0,0 +1,123 @@
+// free text terms of a notmuch query, prefixed terms and operators skipped
+pub fn query_terms(query: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for ch in query.chars().chain(std::iter::once(' ')) {
+        match ch {
+            '"' => {
+                quoted = !quoted;
+                token.push(ch);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(token.to_string());
+                    token.clear();
+                }
+            }
+            c => token.push(c),
+        }
+    }
+
+    tokens
+        .iter()
+        .map(|t| t.trim_matches(|c| c == '(' || c == ')'))
+        .filter(|t| !t.contains(':'))
+        .filter(|t| {
+            let lower = t.to_lowercase();
+            !["and", "or", "not", "xor", "near", "adj", "*"].contains(&lower.as_str())
+        })
+        .map(|t| t.trim_matches('"').trim_end_matches('*').to_string())
+        .filter(|t| t.chars().count() > 1)
+        .collect()
+}
+
+// byte ranges of case insensitive matches of any pattern, sorted
+pub fn find(line: &str, patterns: &[String]) -> Vec<(usize, usize)> {
+    // lowercasing may change byte offsets of some characters, then match case
+    let fold = line
+        .chars()
+        .all(|c| c.to_lowercase().map(char::len_utf8).sum::<usize>() == c.len_utf8());
+    let lower = line.to_lowercase();
+    let haystack = match fold {
+        true => lower.as_str(),
+        _ => line,
+    };
+
+    let mut result = vec![];
+    for pattern in patterns.iter().filter(|p| !p.is_empty()) {
+        let pattern = match fold {
+            true => pattern.to_lowercase(),
+            _ => pattern.to_string(),
+        };
+        let mut start = 0;
+        while let Some(pos) = haystack[start..].find(&pattern) {
+            result.push((start + pos, start + pos + pattern.len()));
+            start += pos + pattern.len();
+        }
+    }
+    result.sort();
+
+    // drop overlapping matches of different patterns
+    let mut merged: Vec<(usize, usize)> = vec![];
+    for (start, end) in result {
+        match merged.last_mut() {
+            Some(last) if start < last.1 => last.1 = std::cmp::max(last.1, end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn free_text_terms_are_found() {
+        assert_eq!(
+            query_terms("tag:inbox and (Kokous OR \"hyvä päivä\") not from:me meet*"),
+            vec!["Kokous", "hyvä päivä", "meet"]
+        );
+        assert!(query_terms("").is_empty());
+        assert!(query_terms("* and a").is_empty());
+    }
+
+    #[test]
+    fn matches_ignore_case() {
+        let line = "Hyvää Päivää, päivää!";
+        let found = find(line, &patterns(&["PÄIVÄÄ"]));
+        assert_eq!(found.len(), 2);
+        for (start, end) in found {
+            assert_eq!(line[start..end].to_lowercase(), "päivää");
+        }
+    }
+
+    #[test]
+    fn overlapping_matches_are_merged() {
+        assert_eq!(
+            find("abcdef", &patterns(&["bcd", "cde", "f"])),
+            vec![(1, 5), (5, 6)]
+        );
+        assert_eq!(find("aaaa", &patterns(&["aa"])), vec![(0, 2), (2, 4)]);
+        assert!(find("", &patterns(&["a"])).is_empty());
+        assert!(find("abc", &patterns(&[""])).is_empty());
+    }
+
+    #[test]
+    fn ranges_stay_on_character_boundaries() {
+        // 'İ' grows and the kelvin sign shrinks when lowercased
+        let line = "İ \u{212a}elvin İstanbul";
+        for (start, end) in find(line, &patterns(&["stan", "elvin"])) {
+            assert!(line.is_char_boundary(start) && line.is_char_boundary(end));
+            assert!(line[start..end] == *"stan" || line[start..end] == *"elvin");
+        }
+        assert_eq!(find(line, &patterns(&["stan", "elvin"])).len(), 2);
+    }
+}
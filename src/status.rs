@@ -0,0 +1,151 @@
+// feedback for the status bar, every message is kept in a log
+use chrono::prelude::*;
+use log::*;
+use std::time::{Duration, Instant};
+
+const LOG_SIZE: usize = 200;
+const INFO_TIMEOUT: Duration = Duration::from_secs(5);
+// errors stay longer so they are not missed
+const ERROR_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+pub struct Entry {
+    pub time: DateTime<Local>,
+    pub level: Level,
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct Status {
+    log: Vec<Entry>,
+    // when the newest entry was added
+    added: Option<Instant>,
+}
+
+impl Status {
+    fn add(&mut self, level: Level, text: String) {
+        debug!("status: {:?} {}", level, text);
+
+        self.log.push(Entry {
+            time: Local::now(),
+            level,
+            text,
+        });
+        if self.log.len() > LOG_SIZE {
+            self.log.remove(0);
+        }
+        self.added = Some(Instant::now());
+    }
+
+    pub fn info<S: Into<String>>(&mut self, text: S) {
+        self.add(Level::Info, text.into());
+    }
+
+    pub fn error<S: Into<String>>(&mut self, text: S) {
+        self.add(Level::Error, text.into());
+    }
+
+    // message on success, error otherwise
+    pub fn report(&mut self, result: Result<String, failure::Error>) {
+        match result {
+            Ok(text) => self.info(text),
+            Err(e) => self.error(e.to_string()),
+        }
+    }
+
+    // time left until the newest entry expires
+    pub fn expires_in(&self) -> Option<Duration> {
+        let entry = self.log.last()?;
+        let timeout = match entry.level {
+            Level::Info => INFO_TIMEOUT,
+            Level::Error => ERROR_TIMEOUT,
+        };
+        timeout.checked_sub(self.added?.elapsed())
+    }
+
+    // newest entry until it expires
+    pub fn transient(&self) -> Option<&Entry> {
+        self.expires_in()?;
+        self.log.last()
+    }
+
+    // whole log as text, oldest first
+    pub fn log(&self) -> String {
+        self.log
+            .iter()
+            .map(|entry| {
+                let level = match entry.level {
+                    Level::Info => "info ",
+                    Level::Error => "error",
+                };
+                format!(
+                    "{} {} {}\n",
+                    entry.time.format("%H:%M:%S"),
+                    level,
+                    entry.text
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_status_has_no_message() {
+        let status = Status::default();
+        assert!(status.transient().is_none());
+        assert!(status.expires_in().is_none());
+        assert_eq!(status.log(), "");
+    }
+
+    #[test]
+    fn newest_entry_is_transient() {
+        let mut status = Status::default();
+        status.info("päivitetty");
+        status.error("failed");
+        let entry = status.transient().unwrap();
+        assert_eq!(entry.level, Level::Error);
+        assert_eq!(entry.text, "failed");
+        assert!(status.expires_in().unwrap() > INFO_TIMEOUT);
+    }
+
+    #[test]
+    fn entries_expire() {
+        let mut status = Status::default();
+        status.info("done");
+        status.added = Some(Instant::now() - INFO_TIMEOUT);
+        assert!(status.transient().is_none());
+        assert!(status.expires_in().is_none());
+        // errors stay longer
+        status.error("failed");
+        status.added = Some(Instant::now() - INFO_TIMEOUT);
+        assert!(status.transient().is_some());
+    }
+
+    #[test]
+    fn report_and_log() {
+        let mut status = Status::default();
+        status.report(Ok("sent".to_string()));
+        status.report(Err(failure::format_err!("no recipients")));
+        let log: Vec<String> = status.log().lines().map(|l| l[9..].to_string()).collect();
+        assert_eq!(log, vec!["info  sent", "error no recipients"]);
+    }
+
+    #[test]
+    fn log_is_capped() {
+        let mut status = Status::default();
+        for i in 0..LOG_SIZE + 10 {
+            status.info(i.to_string());
+        }
+        assert_eq!(status.log.len(), LOG_SIZE);
+        assert_eq!(status.log[0].text, "10");
+    }
+}
@@ -0,0 +1,98 @@
+use log::*;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+// private directory for files opened during the session,
+// removed on drop unless kept for viewers that fork
+pub struct TmpDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TmpDir {
+    pub fn new(keep: bool) -> Result<TmpDir, failure::Error> {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "nutt-{}-{}",
+            std::process::id(),
+            chrono::Local::now().timestamp_subsec_nanos()
+        ));
+        TmpDir::create(path, keep)
+    }
+
+    fn create(path: PathBuf, keep: bool) -> Result<TmpDir, failure::Error> {
+        debug!("TmpDir::create: {:?}", &path);
+
+        // fails if path exists, so it can't be a pre-planted symlink
+        std::fs::DirBuilder::new().mode(0o700).create(&path)?;
+
+        Ok(TmpDir { path, keep })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        if self.keep {
+            debug!("TmpDir::drop: keeping {:?}", &self.path);
+            return;
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            error!("Failed to remove {:?}: {}", &self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    // parent for test directories, removed with its contents
+    fn parent(name: &str) -> TmpDir {
+        let path = std::env::temp_dir().join(format!("nutt-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        TmpDir::create(path, false).unwrap()
+    }
+
+    #[test]
+    fn directory_is_private() {
+        let dir = TmpDir::new(false).unwrap();
+        let meta = std::fs::symlink_metadata(dir.path()).unwrap();
+        assert!(meta.is_dir());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o700);
+    }
+
+    #[test]
+    fn existing_paths_are_not_used() {
+        let parent = parent("exists");
+        let path = parent.path().join("täällä");
+        std::fs::create_dir(&path).unwrap();
+        assert!(TmpDir::create(path.clone(), true).is_err());
+        // planted symlink isn't followed
+        let link = parent.path().join("link");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        assert!(TmpDir::create(link, true).is_err());
+    }
+
+    #[test]
+    fn dropped_directory_is_removed() {
+        let parent = parent("drop");
+        let path = parent.path().join("dir");
+        let dir = TmpDir::create(path.clone(), false).unwrap();
+        std::fs::write(dir.path().join("file.txt"), b"x").unwrap();
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn kept_directory_stays() {
+        let parent = parent("keep");
+        let path = parent.path().join("dir");
+        drop(TmpDir::create(path.clone(), true).unwrap());
+        assert!(path.is_dir());
+    }
+}
@@ -0,0 +1,112 @@
+// terminal display width of text, wide characters take two columns
+// and combining sequences are never split
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+pub fn width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+// longest prefix fitting in max columns
+pub fn truncate(text: &str, max: usize) -> &str {
+    let mut used = 0;
+    for (pos, grapheme) in text.grapheme_indices(true) {
+        used += width(grapheme);
+        if used > max {
+            return &text[..pos];
+        }
+    }
+    text
+}
+
+// text padded with spaces to width columns
+pub fn pad(text: &str, width: usize) -> String {
+    let fill = width.saturating_sub(self::width(text));
+    format!("{}{}", text, " ".repeat(fill))
+}
+
+// line broken at whitespace to parts of at most width columns,
+// longer words are split, later parts begin with 'prefix' that
+// the line itself starts with
+pub fn wrap(line: &str, width: usize, prefix: &str) -> Vec<String> {
+    let mut result = vec![];
+    let mut rest = line.to_string();
+
+    while self::width(&rest) > width && width > self::width(prefix) {
+        let fits = truncate(&rest, width);
+        let split = match fits.rmatch_indices(char::is_whitespace).next() {
+            // whole words fit when a space follows
+            _ if rest[fits.len()..].starts_with(char::is_whitespace) => fits.len(),
+            Some((pos, space)) if pos > prefix.len() => pos + space.len(),
+            _ if fits.len() > prefix.len() => fits.len(),
+            // wide grapheme that doesn't fit, still make progress
+            _ => {
+                fits.len()
+                    + rest[fits.len()..]
+                        .graphemes(true)
+                        .next()
+                        .map_or(0, |g| g.len())
+            }
+        };
+        result.push(rest[..split].trim_end().to_string());
+        rest = format!("{}{}", prefix, rest[split..].trim_start());
+    }
+    // nothing left after the last split
+    if result.is_empty() || rest != prefix {
+        result.push(rest);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(width(""), 0);
+        assert_eq!(width("abc"), 3);
+        assert_eq!(width("日本"), 4);
+        assert_eq!(width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn truncate_keeps_graphemes_whole() {
+        assert_eq!(truncate("", 3), "");
+        assert_eq!(truncate("abc", 0), "");
+        assert_eq!(truncate("abc", 3), "abc");
+        assert_eq!(truncate("日本語", 3), "日");
+        assert_eq!(truncate("日本語", 4), "日本");
+        assert_eq!(truncate("ae\u{301}b", 2), "ae\u{301}");
+        assert_eq!(truncate("a👍b", 2), "a");
+    }
+
+    #[test]
+    fn pad_fills_to_columns() {
+        assert_eq!(pad("日本", 6), "日本  ");
+        assert_eq!(pad("", 2), "  ");
+        assert_eq!(pad("long", 2), "long");
+    }
+
+    #[test]
+    fn wrap_breaks_at_whitespace() {
+        assert_eq!(wrap("", 10, ""), vec![""]);
+        assert_eq!(wrap("short", 10, ""), vec!["short"]);
+        assert_eq!(wrap("one two three", 8, ""), vec!["one two", "three"]);
+        assert_eq!(
+            wrap("> one two three", 9, "> "),
+            vec!["> one two", "> three"]
+        );
+    }
+
+    #[test]
+    fn long_words_are_split() {
+        assert_eq!(wrap("abcdef", 4, ""), vec!["abcd", "ef"]);
+        assert_eq!(wrap("日本語", 4, ""), vec!["日本", "語"]);
+        // wide character doesn't fit at all but wrapping still ends
+        assert_eq!(wrap("日本", 1, ""), vec!["日", "本"]);
+        // no room after the prefix, line is kept
+        assert_eq!(wrap("> abc", 2, "> "), vec!["> abc"]);
+    }
+}